@@ -0,0 +1,85 @@
+use crate::class::CharClass;
+
+/// The bytes `.`, negated sets and `\D`, `\W` and `\S` choose from when a
+/// pattern is generated. Other sets and literals don't have to keep to it,
+/// so `[\t ]` still produces a tab over printable ASCII.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Alphabet {
+    class: CharClass,
+    universe: CharClass,
+    dot_newline: bool,
+}
+
+impl Default for Alphabet {
+    fn default() -> Self {
+        Alphabet::printable()
+    }
+}
+
+impl Alphabet {
+    /// Printable ASCII, from space to `~`.
+    pub const fn printable() -> Self {
+        Alphabet::custom(CharClass::range(b' ', b'~'))
+    }
+
+    /// Every byte value.
+    pub const fn bytes() -> Self {
+        Alphabet::custom(CharClass::all())
+    }
+
+    /// Printable ASCII along with tab, newline, vertical tab, form feed and
+    /// carriage return.
+    pub const fn ascii() -> Self {
+        Alphabet::custom(CharClass::range(b' ', b'~').union(CharClass::range(b'\t', b'\r')))
+    }
+
+    pub const fn custom(class: CharClass) -> Self {
+        Alphabet {
+            class,
+            universe: CharClass::all(),
+            dot_newline: false,
+        }
+    }
+
+    /// Let `.` produce `\n` when the alphabet has it. Off by default, as
+    /// `.` doesn't match a newline.
+    pub fn dot_newline(mut self, dot_newline: bool) -> Self {
+        self.dot_newline = dot_newline;
+        self
+    }
+
+    /// Keep every generated byte inside `universe`, like regldg's
+    /// `--universe-set`: the alphabet and every set lose what is outside
+    /// it, and branches left with nothing to produce are dropped. Parse
+    /// with [`parse_in`](crate::parse_in) to refuse patterns that name a
+    /// byte outside it.
+    pub fn within(mut self, universe: CharClass) -> Self {
+        self.class = self.class.intersection(universe);
+        self.universe = self.universe.intersection(universe);
+        self
+    }
+
+    pub fn universe(&self) -> CharClass {
+        self.universe
+    }
+
+    pub fn class(&self) -> CharClass {
+        self.class
+    }
+
+    /// What `.` can produce.
+    pub fn dot(&self) -> CharClass {
+        match self.dot_newline {
+            true => self.class,
+            false => self
+                .class
+                .intersection(CharClass::from_bytes(b"\n").complement()),
+        }
+    }
+
+    /// What a set negated with `^`, or a negated escape like `\D`, can
+    /// produce.
+    pub fn except(&self, class: CharClass) -> CharClass {
+        self.class.intersection(class.complement())
+    }
+}
@@ -0,0 +1,52 @@
+use crate::class::CharClass;
+
+/// What a parenthesised group does besides grouping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroupKind {
+    /// `(..)` or a named group, with its capture index counting from 1 in
+    /// the order the groups open.
+    Capture(usize),
+    /// `(?:..)`
+    NonCapture,
+    /// `(?=..)`, or `(?!..)` when negative.
+    Lookahead { negative: bool },
+    /// `(?<=..)`, or `(?<!..)` when negative.
+    Lookbehind { negative: bool },
+    /// `(?>..)`
+    Atomic,
+}
+
+/// A node of the pattern tree.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    /// Matches the empty string, like an empty alternative in `a|`.
+    Empty,
+    /// The `^` anchor, which produces nothing.
+    Start,
+    /// The `$` anchor, which produces nothing.
+    End,
+    /// `.`, any one byte of the alphabet the pattern is generated over.
+    Any,
+    /// These bytes, in order.
+    Text(Vec<u8>),
+    /// One byte out of `class`, or one byte of the alphabet not in it when
+    /// `negated`.
+    Charset {
+        class: CharClass,
+        negated: bool,
+    },
+    /// Each node in turn.
+    Seq(Vec<Node>),
+    /// Any one of the nodes, as in `a|b`.
+    Select(Vec<Node>),
+    /// `node` repeated from `min` to `max` times, with no upper bound when
+    /// `max` is `None`.
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+    Group(Box<Node>, GroupKind),
+    /// A backreference to the capture group with this index.
+    Ref(usize),
+}
@@ -0,0 +1,65 @@
+use crate::ast::Node;
+use crate::error::ParseError;
+
+/// A pattern from a pattern file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// The line the pattern is on, counting from 1.
+    pub line: usize,
+    pub pattern: Vec<u8>,
+}
+
+impl Entry {
+    pub fn parse(&self) -> Result<Node, ParseError> {
+        crate::parse(&self.pattern)
+    }
+}
+
+/// The patterns in a pattern file, one per line. Empty lines and lines
+/// starting with `#` are skipped; a `#` anywhere else is part of the
+/// pattern. Line breaks may be `\n` or `\r\n`.
+pub fn patterns(text: &[u8]) -> Vec<Entry> {
+    text.split(|&ch| ch == b'\n')
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.strip_suffix(b"\r").unwrap_or(line)))
+        .filter(|(_, line)| !line.is_empty() && line[0] != b'#')
+        .map(|(line, pattern)| Entry {
+            line,
+            pattern: pattern.to_vec(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+
+    #[test]
+    fn patterns_a() {
+        let text = b"# dates\n\\d{4}-\\d\\d\r\n\n  # not a comment\na(\n#\nx#y";
+        let entries = patterns(text);
+        let lines: Vec<_> = entries.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [2, 4, 5, 7]);
+        assert_eq!(entries[0].pattern, br"\d{4}-\d\d");
+        assert_eq!(entries[1].pattern, b"  # not a comment");
+        assert_eq!(
+            entries[2].parse().unwrap_err().kind,
+            ErrorKind::UnbalancedParen
+        );
+        assert!(entries[3].parse().is_ok());
+    }
+
+    #[test]
+    fn patterns_b() {
+        let mut text = b"# parse_regexp corpus\n".to_vec();
+        for re in crate::CORPUS {
+            text.extend_from_slice(re.as_bytes());
+            text.push(b'\n');
+        }
+        let entries = patterns(&text);
+        assert_eq!(entries.len(), crate::CORPUS.len());
+        assert!(entries.iter().all(|e| e.parse().is_ok()));
+        assert_eq!(entries[0].line, 2);
+    }
+}
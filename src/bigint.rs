@@ -0,0 +1,296 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, Sub};
+
+/// Arbitrary precision unsigned integer, stored as little-endian 32 bit
+/// digits with no trailing zero digits.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BigUint {
+    digits: Vec<u32>,
+}
+
+impl BigUint {
+    pub fn zero() -> Self {
+        Self { digits: Vec::new() }
+    }
+
+    pub fn one() -> Self {
+        Self::from(1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    /// Number of significant bits.
+    pub fn bits(&self) -> usize {
+        match self.digits.last() {
+            Some(top) => self.digits.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    /// The value as a `u64`, if it fits.
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.digits.len() {
+            0 => Some(0),
+            1 => Some(self.digits[0] as u64),
+            2 => Some(self.digits[0] as u64 | (self.digits[1] as u64) << 32),
+            _ => None,
+        }
+    }
+
+    /// Quotient and remainder of division by a small, non-zero divisor.
+    pub fn div_rem_small(&self, divisor: u32) -> (BigUint, u32) {
+        let mut digits = self.digits.clone();
+        let mut rem = 0u64;
+        for digit in digits.iter_mut().rev() {
+            let cur = rem << 32 | *digit as u64;
+            *digit = (cur / divisor as u64) as u32;
+            rem = cur % divisor as u64;
+        }
+        (BigUint::from_digits(digits), rem as u32)
+    }
+
+    /// Quotient and remainder of division by a non-zero divisor.
+    pub fn div_rem(&self, divisor: &BigUint) -> (BigUint, BigUint) {
+        assert!(!divisor.is_zero(), "BigUint division by zero");
+        if self < divisor {
+            return (BigUint::zero(), self.clone());
+        }
+        if divisor.digits.len() == 1 {
+            let (quot, rem) = self.div_rem_small(divisor.digits[0]);
+            return (quot, BigUint::from(rem as u64));
+        }
+        // Plain binary long division, one bit of the dividend at a time
+        let mut quot = vec![0u32; self.digits.len()];
+        let mut rem = BigUint::zero();
+        for bit in (0..self.bits()).rev() {
+            rem.shift_in(self.digits[bit / 32] >> (bit % 32) & 1);
+            if rem >= *divisor {
+                rem = &rem - divisor;
+                quot[bit / 32] |= 1 << (bit % 32);
+            }
+        }
+        (BigUint::from_digits(quot), rem)
+    }
+
+    /// Subtract one in place, which unlike `-` needn't allocate. Zero stays
+    /// zero.
+    pub(crate) fn decrement(&mut self) {
+        for digit in self.digits.iter_mut() {
+            let borrow = *digit == 0;
+            *digit = digit.wrapping_sub(1);
+            if !borrow {
+                break;
+            }
+        }
+        self.trim();
+    }
+
+    /// Shift left by one bit, filling the low bit with `bit`.
+    fn shift_in(&mut self, bit: u32) {
+        let mut carry = bit;
+        for digit in self.digits.iter_mut() {
+            let next = *digit >> 31;
+            *digit = *digit << 1 | carry;
+            carry = next;
+        }
+        if carry > 0 {
+            self.digits.push(carry);
+        }
+    }
+
+    pub(crate) fn from_digits(digits: Vec<u32>) -> Self {
+        let mut n = Self { digits };
+        n.trim();
+        n
+    }
+
+    fn trim(&mut self) {
+        while let Some(0) = self.digits.last() {
+            self.digits.pop();
+        }
+    }
+}
+
+impl From<u64> for BigUint {
+    fn from(n: u64) -> Self {
+        Self::from_digits(vec![n as u32, (n >> 32) as u32])
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const CHUNK: u32 = 1_000_000_000;
+        let mut chunks = Vec::new();
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let (quot, rem) = n.div_rem_small(CHUNK);
+            chunks.push(rem);
+            n = quot;
+        }
+        let mut s = chunks.pop().unwrap_or(0).to_string();
+        for chunk in chunks.iter().rev() {
+            s.push_str(&format!("{:09}", chunk));
+        }
+        f.pad_integral(true, "", &s)
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.digits
+            .len()
+            .cmp(&other.digits.len())
+            .then_with(|| self.digits.iter().rev().cmp(other.digits.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl AddAssign<&BigUint> for BigUint {
+    fn add_assign(&mut self, other: &BigUint) {
+        if self.digits.len() < other.digits.len() {
+            self.digits.resize(other.digits.len(), 0);
+        }
+        let mut carry = 0u64;
+        for (i, digit) in self.digits.iter_mut().enumerate() {
+            let sum = *digit as u64 + *other.digits.get(i).unwrap_or(&0) as u64 + carry;
+            *digit = sum as u32;
+            carry = sum >> 32;
+            if carry == 0 && i >= other.digits.len() {
+                break;
+            }
+        }
+        if carry > 0 {
+            self.digits.push(carry as u32);
+        }
+    }
+}
+
+impl<'a> Add<&'a BigUint> for &'a BigUint {
+    type Output = BigUint;
+
+    fn add(self, other: &BigUint) -> BigUint {
+        let mut sum = self.clone();
+        sum += other;
+        sum
+    }
+}
+
+impl<'a> Sub<&'a BigUint> for &'a BigUint {
+    type Output = BigUint;
+
+    /// Panics if `other` is larger than `self`.
+    fn sub(self, other: &BigUint) -> BigUint {
+        assert!(*self >= *other, "BigUint subtraction underflow");
+        let mut digits = self.digits.clone();
+        let mut borrow = 0i64;
+        for (i, digit) in digits.iter_mut().enumerate() {
+            let diff = *digit as i64 - *other.digits.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = if diff < 0 { 1 } else { 0 };
+            *digit = (diff + (borrow << 32)) as u32;
+        }
+        BigUint::from_digits(digits)
+    }
+}
+
+impl<'a> Mul<&'a BigUint> for &'a BigUint {
+    type Output = BigUint;
+
+    fn mul(self, other: &BigUint) -> BigUint {
+        if self.is_zero() || other.is_zero() {
+            return BigUint::zero();
+        }
+        let mut digits = vec![0u32; self.digits.len() + other.digits.len()];
+        for (i, &a) in self.digits.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.digits.iter().enumerate() {
+                let product = a as u64 * b as u64 + digits[i + j] as u64 + carry;
+                digits[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            digits[i + other.digits.len()] = carry as u32;
+        }
+        BigUint::from_digits(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bigint_a() {
+        let a = BigUint::from(u64::MAX);
+        let b = &a + &BigUint::one();
+        assert_eq!(b.bits(), 65);
+        assert_eq!(b.to_u64(), None);
+        assert_eq!(a.to_u64(), Some(u64::MAX));
+        assert_eq!(&b - &BigUint::one(), a);
+        assert_eq!(&(&b - &a) - &BigUint::one(), BigUint::zero());
+        assert!(b > a);
+        assert!(BigUint::zero() < BigUint::one());
+        let mut c = b.clone();
+        c.decrement();
+        assert_eq!(c, a);
+        let mut one = BigUint::one();
+        one.decrement();
+        assert!(one.is_zero());
+        one.decrement();
+        assert!(one.is_zero());
+    }
+
+    #[test]
+    fn bigint_b() {
+        let a = BigUint::from(0xffff_ffff);
+        assert_eq!(&a * &a, BigUint::from(0xffff_fffe_0000_0001));
+        let mut big = BigUint::one();
+        for _ in 0..18 {
+            big = &big * &BigUint::from(38);
+        }
+        assert_eq!(big.bits(), 95);
+        let mut sum = BigUint::zero();
+        (0..38).for_each(|_| sum += &big);
+        assert_eq!(sum, &big * &BigUint::from(38));
+    }
+
+    #[test]
+    fn bigint_c() {
+        assert_eq!(BigUint::zero().to_string(), "0");
+        assert_eq!(BigUint::from(1_000_000_007).to_string(), "1000000007");
+        let mut big = BigUint::one();
+        for _ in 0..30 {
+            big = &big * &BigUint::from(10);
+        }
+        assert_eq!(big.to_string(), format!("1{}", "0".repeat(30)));
+        assert_eq!(format!("{:>4}", BigUint::from(42)), "  42");
+        assert_eq!(big.div_rem_small(7).1, 1);
+    }
+
+    #[test]
+    fn bigint_d() {
+        let a = &BigUint::from(u64::MAX) * &BigUint::from(1_000_000_007);
+        let b = &BigUint::from(u64::MAX) + &BigUint::from(12);
+        let (q, r) = (&a + &BigUint::from(5)).div_rem(&b);
+        assert_eq!(&(&q * &b) + &r, &a + &BigUint::from(5));
+        assert!(r < b);
+        assert_eq!(
+            a.div_rem(&BigUint::from(u64::MAX)),
+            (BigUint::from(1_000_000_007), BigUint::zero())
+        );
+        assert_eq!(
+            BigUint::from(3).div_rem(&b),
+            (BigUint::zero(), BigUint::from(3))
+        );
+        assert_eq!(
+            BigUint::from(30).div_rem(&BigUint::from(7)),
+            (BigUint::from(4), BigUint::from(2))
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::RangeInclusive;
+
+/// A set of bytes, stored as one bit per byte value.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CharClass([u64; 4]);
+
+/// `\d`
+pub const DIGIT: CharClass = CharClass::range(b'0', b'9');
+/// `\w`
+pub const WORD: CharClass = CharClass::range(b'A', b'Z')
+    .union(CharClass::range(b'a', b'z'))
+    .union(DIGIT)
+    .union(CharClass::from_bytes(b"_"));
+/// `\s`
+pub const SPACE: CharClass = CharClass::from_bytes(b"\t ");
+
+impl CharClass {
+    /// The empty set.
+    pub const fn new() -> Self {
+        CharClass([0; 4])
+    }
+
+    /// Every byte value.
+    pub const fn all() -> Self {
+        CharClass([u64::MAX; 4])
+    }
+
+    /// The bytes from `from` to `to` inclusive, or none if `to` comes
+    /// first.
+    pub const fn range(from: u8, to: u8) -> Self {
+        let mut class = CharClass::new();
+        let mut ch = from as usize;
+        while ch <= to as usize {
+            class.0[ch / 64] |= 1 << (ch % 64);
+            ch += 1;
+        }
+        class
+    }
+
+    pub const fn from_bytes(bytes: &[u8]) -> Self {
+        let mut class = CharClass::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            class.0[bytes[i] as usize / 64] |= 1 << (bytes[i] % 64);
+            i += 1;
+        }
+        class
+    }
+
+    pub fn insert(&mut self, ch: u8) {
+        self.0[ch as usize / 64] |= 1 << (ch % 64);
+    }
+
+    pub const fn contains(&self, ch: u8) -> bool {
+        self.0[ch as usize / 64] & 1 << (ch % 64) != 0
+    }
+
+    pub const fn union(self, other: CharClass) -> Self {
+        let (a, b) = (self.0, other.0);
+        CharClass([a[0] | b[0], a[1] | b[1], a[2] | b[2], a[3] | b[3]])
+    }
+
+    pub const fn intersection(self, other: CharClass) -> Self {
+        let (a, b) = (self.0, other.0);
+        CharClass([a[0] & b[0], a[1] & b[1], a[2] & b[2], a[3] & b[3]])
+    }
+
+    /// Every byte not in the set.
+    pub const fn complement(self) -> Self {
+        let a = self.0;
+        CharClass([!a[0], !a[1], !a[2], !a[3]])
+    }
+
+    /// Number of bytes in the set.
+    pub const fn len(&self) -> usize {
+        let a = self.0;
+        (a[0].count_ones() + a[1].count_ones() + a[2].count_ones() + a[3].count_ones()) as usize
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `n`th byte of the set in ascending order, counting from 0.
+    pub fn nth(&self, mut n: usize) -> Option<u8> {
+        for (i, &word) in self.0.iter().enumerate() {
+            let ones = word.count_ones() as usize;
+            if n < ones {
+                // Drop the lowest `n` bits that are set
+                let mut word = word;
+                for _ in 0..n {
+                    word &= word - 1;
+                }
+                return Some((i * 64) as u8 + word.trailing_zeros() as u8);
+            }
+            n -= ones;
+        }
+        None
+    }
+
+    /// Where `ch` comes in the set in ascending order, if it is in it.
+    pub fn index(&self, ch: u8) -> Option<usize> {
+        if !self.contains(ch) {
+            return None;
+        }
+        let word = ch as usize / 64;
+        let below = self.0[word] & ((1 << (ch % 64)) - 1);
+        let before: u32 = self.0[..word].iter().map(|w| w.count_ones()).sum();
+        Some((before + below.count_ones()) as usize)
+    }
+
+    /// The bytes of the set in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=u8::MAX).filter(move |&ch| self.contains(ch))
+    }
+
+    /// The set as the fewest ranges of consecutive bytes, in ascending
+    /// order.
+    pub fn ranges(&self) -> Vec<RangeInclusive<u8>> {
+        let mut ranges: Vec<RangeInclusive<u8>> = Vec::new();
+        for ch in self.iter() {
+            match ranges.last_mut() {
+                Some(last) if *last.end() as usize + 1 == ch as usize => {
+                    *last = *last.start()..=ch;
+                }
+                _ => ranges.push(ch..=ch),
+            }
+        }
+        ranges
+    }
+}
+
+impl FromIterator<u8> for CharClass {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        let mut class = CharClass::new();
+        iter.into_iter().for_each(|ch| class.insert(ch));
+        class
+    }
+}
+
+impl fmt::Debug for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |ch: u8| std::ascii::escape_default(ch).to_string();
+        f.write_str("[")?;
+        for range in self.ranges() {
+            match range.end() - range.start() {
+                0 => write!(f, "{}", show(*range.start()))?,
+                _ => write!(f, "{}-{}", show(*range.start()), show(*range.end()))?,
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn class_a() {
+        let az = CharClass::range(b'a', b'z');
+        assert_eq!(az.len(), 26);
+        assert_eq!(CharClass::range(b'z', b'a'), CharClass::new());
+        assert!(CharClass::new().is_empty());
+        assert_eq!(WORD.len(), 63);
+        assert_eq!(az.union(DIGIT).len(), 36);
+        assert_eq!(WORD.intersection(az), az);
+        assert_eq!(az.complement().len(), 230);
+        assert!(!az.complement().contains(b'q') && az.complement().contains(0xff));
+        assert_eq!(format!("{:?}", SPACE.union(DIGIT)), r"[\t 0-9]");
+    }
+
+    #[test]
+    fn class_b() {
+        let class: CharClass = [0u8, 3, 64, 65, 200, 255].iter().cloned().collect();
+        assert_eq!(class.iter().collect::<Vec<_>>(), [0, 3, 64, 65, 200, 255]);
+        for (i, ch) in class.iter().enumerate() {
+            assert_eq!(class.nth(i), Some(ch));
+            assert_eq!(class.index(ch), Some(i));
+        }
+        assert_eq!(class.nth(6), None);
+        assert_eq!(class.index(1), None);
+        assert_eq!(
+            class.ranges(),
+            [0..=0, 3..=3, 64..=65, 200..=200, 255..=255]
+        );
+        let all = CharClass::new().complement();
+        assert_eq!(
+            (all.len(), all.nth(255), all.index(255)),
+            (256, Some(255), Some(255))
+        );
+        assert_eq!(all.ranges(), [0..=255]);
+    }
+}
@@ -0,0 +1,491 @@
+use crate::bigint::BigUint;
+use crate::class::CharClass;
+use crate::enumerate::Enumerator;
+use crate::expr::Expr;
+use crate::rank::Counts;
+use crate::rng::Rng;
+use crate::uniform::choose;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+/// Ways of producing strings a pattern with backreferences may have for
+/// its strings to be listed and counted exactly.
+const LISTED: u64 = 1 << 18;
+
+/// Byte automaton with empty moves, built so that every string up to a
+/// length limit has a path from the start state to the end state.
+struct Nfa<'a> {
+    empty: Vec<Vec<usize>>,
+    bytes: Vec<Vec<(u8, usize)>>,
+    root: &'a Expr,
+    /// Groups whose copy for a backreference is being built.
+    copying: Vec<usize>,
+}
+
+impl<'a> Nfa<'a> {
+    /// The automaton for `expr` up to `max_len` bytes, starting at state 0,
+    /// and its end state.
+    fn of(expr: &'a Expr, max_len: usize) -> (Self, usize) {
+        let mut nfa = Nfa {
+            empty: Vec::new(),
+            bytes: Vec::new(),
+            root: expr,
+            copying: Vec::new(),
+        };
+        let start = nfa.state();
+        let end = nfa.build(expr, start, max_len);
+        (nfa, end)
+    }
+
+    fn state(&mut self) -> usize {
+        self.empty.push(Vec::new());
+        self.bytes.push(Vec::new());
+        self.empty.len() - 1
+    }
+
+    /// Add the paths for `expr` leaving `from`, returning where they end.
+    fn build(&mut self, expr: &Expr, from: usize, max_len: usize) -> usize {
+        match expr {
+            Expr::Empty => from,
+            Expr::Byte(ch) => {
+                let to = self.state();
+                self.bytes[from].push((*ch, to));
+                to
+            }
+            Expr::Class(set) => {
+                let to = self.state();
+                self.bytes[from].extend(set.iter().map(|ch| (ch, to)));
+                to
+            }
+            Expr::Seq(exprs) => exprs.iter().fold(from, |at, e| self.build(e, at, max_len)),
+            Expr::Alt(exprs) => {
+                let to = self.state();
+                for e in exprs {
+                    let start = self.state();
+                    self.empty[from].push(start);
+                    let end = self.build(e, start, max_len);
+                    self.empty[end].push(to);
+                }
+                to
+            }
+            Expr::Repeat(inner, min, max) => {
+                // Past the length limit more iterations can only add empty
+                // matches, which don't produce new strings
+                let (least, most) = match inner.min_len() {
+                    _ if min > max => return self.state(),
+                    None if *min > 0 => return self.state(),
+                    None => (0, 0),
+                    Some(0) => (0, (*max).min(max_len)),
+                    Some(len) if min.saturating_mul(len) > max_len => return self.state(),
+                    Some(len) => (*min, (*max).min(max_len / len)),
+                };
+                let mut at = from;
+                for _ in 0..least {
+                    at = self.build(inner, at, max_len);
+                }
+                let to = self.state();
+                self.empty[at].push(to);
+                for _ in least..most {
+                    at = self.build(inner, at, max_len);
+                    self.empty[at].push(to);
+                }
+                to
+            }
+            Expr::Group(_, inner) => self.build(inner, from, max_len),
+            Expr::Ref(n) => {
+                // An automaton can't remember what the group matched, so
+                // this allows anything the group could match instead, or
+                // nothing for when it took no part
+                let root: &'a Expr = self.root;
+                match root.group(*n) {
+                    Some(group) if !self.copying.contains(n) => {
+                        self.copying.push(*n);
+                        let to = self.build(group, from, max_len);
+                        self.copying.pop();
+                        self.empty[from].push(to);
+                        to
+                    }
+                    // A group referring to itself can repeat what it
+                    // matched any number of times, so allow any run of
+                    // the bytes the pattern produces
+                    Some(_) => {
+                        let to = self.state();
+                        self.empty[from].push(to);
+                        for ch in produced(root).iter() {
+                            self.bytes[to].push((ch, to));
+                        }
+                        to
+                    }
+                    None => from,
+                }
+            }
+        }
+    }
+
+    /// Sorted set of states reachable from `states` by empty moves.
+    fn closure(&self, mut states: Vec<usize>) -> Vec<usize> {
+        let mut seen = states.clone();
+        while let Some(s) = states.pop() {
+            for &t in &self.empty[s] {
+                if !seen.contains(&t) {
+                    seen.push(t);
+                    states.push(t);
+                }
+            }
+        }
+        seen.sort_unstable();
+        seen.dedup();
+        seen
+    }
+}
+
+/// Deterministic automaton built lazily from the sets of NFA states.
+struct Dfa<'a> {
+    nfa: &'a Nfa<'a>,
+    end: usize,
+    sets: Vec<Vec<usize>>,
+    ids: HashMap<Vec<usize>, usize>,
+    /// For each state, the state each byte leads to.
+    edges: Vec<Option<Vec<(u8, usize)>>>,
+    /// For each state, where it goes and over how many distinct bytes.
+    moves: Vec<Option<Vec<(usize, u64)>>>,
+}
+
+impl<'a> Dfa<'a> {
+    /// The automaton for `nfa`, with its start as state 0.
+    fn new(nfa: &'a Nfa<'a>, end: usize) -> Self {
+        let mut dfa = Dfa {
+            nfa,
+            end,
+            sets: Vec::new(),
+            ids: HashMap::new(),
+            edges: Vec::new(),
+            moves: Vec::new(),
+        };
+        dfa.id(nfa.closure(vec![0]));
+        dfa
+    }
+
+    fn id(&mut self, set: Vec<usize>) -> usize {
+        if let Some(&id) = self.ids.get(&set) {
+            return id;
+        }
+        self.sets.push(set.clone());
+        self.edges.push(None);
+        self.moves.push(None);
+        self.ids.insert(set, self.sets.len() - 1);
+        self.sets.len() - 1
+    }
+
+    fn accepts(&self, id: usize) -> bool {
+        self.sets[id].binary_search(&self.end).is_ok()
+    }
+
+    fn edges(&mut self, id: usize) -> Vec<(u8, usize)> {
+        if let Some(edges) = &self.edges[id] {
+            return edges.clone();
+        }
+        let mut targets: Vec<Vec<usize>> = vec![Vec::new(); 256];
+        for &s in &self.sets[id] {
+            for &(ch, t) in &self.nfa.bytes[s] {
+                targets[ch as usize].push(t);
+            }
+        }
+        let mut edges = Vec::new();
+        for (ch, target) in targets.into_iter().enumerate() {
+            if !target.is_empty() {
+                edges.push((ch as u8, self.id(self.nfa.closure(target))));
+            }
+        }
+        self.edges[id] = Some(edges.clone());
+        edges
+    }
+
+    fn moves(&mut self, id: usize) -> Vec<(usize, u64)> {
+        if let Some(moves) = &self.moves[id] {
+            return moves.clone();
+        }
+        let mut moves: Vec<(usize, u64)> = Vec::new();
+        for (_, to) in self.edges(id) {
+            match moves.iter_mut().find(|(t, _)| *t == to) {
+                Some((_, n)) => *n += 1,
+                None => moves.push((to, 1)),
+            }
+        }
+        self.moves[id] = Some(moves.clone());
+        moves
+    }
+}
+
+/// Every byte `expr` can produce.
+fn produced(expr: &Expr) -> CharClass {
+    match expr {
+        Expr::Empty | Expr::Ref(_) => CharClass::new(),
+        Expr::Byte(ch) => CharClass::from_bytes(&[*ch]),
+        Expr::Class(set) => *set,
+        Expr::Seq(exprs) | Expr::Alt(exprs) => exprs
+            .iter()
+            .fold(CharClass::new(), |set, e| set.union(produced(e))),
+        Expr::Repeat(expr, _, _) | Expr::Group(_, expr) => produced(expr),
+    }
+}
+
+/// How many distinct strings a pattern matches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Count {
+    pub strings: BigUint,
+    /// False if backreferences made an exact count too costly, so that
+    /// `strings` is only an upper bound.
+    pub exact: bool,
+}
+
+/// Number of distinct strings the expression matches with a length in
+/// `lengths`.
+///
+/// Strings are counted once however many ways the pattern can produce
+/// them, so `(a|a)` counts one string and `(\d|[1-9]\d|\d\d)` counts 110.
+/// This works by walking the deterministic automaton of the pattern one
+/// length at a time, keeping a count per automaton state, which never
+/// lists the strings themselves.
+///
+/// An automaton can't tie a backreference to what its group matched, so
+/// patterns using them are counted exactly by listing their strings when
+/// they have few enough. Otherwise each backreference is taken to match
+/// anything its group can, or nothing, which gives an upper bound.
+pub fn count(expr: &Expr, lengths: RangeInclusive<usize>) -> Count {
+    if lengths.is_empty() {
+        return Count {
+            strings: BigUint::zero(),
+            exact: true,
+        };
+    }
+    if expr.has_refs() {
+        let cap = BigUint::from(LISTED);
+        // A group referring to itself makes strings double in length, so
+        // listing them is only done when their length is bounded
+        let listed = expr.max_len().is_some() && Counts::new(Some(cap.clone())).of(expr) < cap;
+        return match listed {
+            true => {
+                let strings: HashSet<_> = Enumerator::new(expr)
+                    .filter(|s| lengths.contains(&s.len()))
+                    .collect();
+                Count {
+                    strings: BigUint::from(strings.len() as u64),
+                    exact: true,
+                }
+            }
+            false => Count {
+                strings: automaton(expr, lengths),
+                exact: false,
+            },
+        };
+    }
+    Count {
+        strings: automaton(expr, lengths),
+        exact: true,
+    }
+}
+
+/// Strings with a length in `lengths` accepted by the automaton of `expr`.
+fn automaton(expr: &Expr, lengths: RangeInclusive<usize>) -> BigUint {
+    let mut total = BigUint::zero();
+    for (_, n) in by_length(expr, *lengths.end())
+        .iter()
+        .enumerate()
+        .filter(|(len, _)| lengths.contains(len))
+    {
+        total += n;
+    }
+    total
+}
+
+/// Strings accepted by the automaton of `expr` of each length, from zero
+/// up to `max_len`, stopping early once no longer string is possible.
+pub(crate) fn by_length(expr: &Expr, max_len: usize) -> Vec<BigUint> {
+    let (nfa, end) = Nfa::of(expr, max_len);
+    let mut dfa = Dfa::new(&nfa, end);
+    let mut layer = vec![(0, BigUint::one())];
+    let mut counts = Vec::new();
+    for len in 0..=max_len {
+        let mut accepted = BigUint::zero();
+        for (id, n) in &layer {
+            if dfa.accepts(*id) {
+                accepted += n;
+            }
+        }
+        counts.push(accepted);
+        if len == max_len {
+            break;
+        }
+        let mut next: HashMap<usize, BigUint> = HashMap::new();
+        for (id, n) in &layer {
+            for (to, ways) in dfa.moves(*id) {
+                *next.entry(to).or_default() += &(n * &BigUint::from(ways));
+            }
+        }
+        if next.is_empty() {
+            break;
+        }
+        layer = next.into_iter().collect();
+    }
+    counts
+}
+
+/// The automaton of `expr` up to a length, with the number of ways to
+/// finish a string from each state, for drawing strings uniformly.
+///
+/// Each string has one path through a deterministic automaton however
+/// many ways the pattern produces it, so walking it with every byte
+/// weighted by the strings it leads to gives each string the same chance.
+pub(crate) struct Paths {
+    /// For each state, the state each byte leads to.
+    edges: Vec<Vec<(u8, usize)>>,
+    /// For each length, how many strings of that length lead from each
+    /// state to acceptance.
+    ahead: Vec<Vec<BigUint>>,
+}
+
+impl Paths {
+    pub fn new(expr: &Expr, max_len: usize) -> Self {
+        let (nfa, end) = Nfa::of(expr, max_len);
+        let mut dfa = Dfa::new(&nfa, end);
+        let (mut reached, mut layer) = (HashSet::new(), vec![0]);
+        reached.insert(0);
+        for _ in 0..max_len {
+            let mut next = Vec::new();
+            for id in layer {
+                next.extend(dfa.edges(id).into_iter().filter_map(
+                    |(_, to)| match reached.insert(to) {
+                        true => Some(to),
+                        false => None,
+                    },
+                ));
+            }
+            layer = next;
+        }
+        // States first reached at the limit are never left, so they need
+        // no moves of their own
+        let moves: Vec<_> = (0..dfa.sets.len())
+            .map(|id| match dfa.edges[id] {
+                Some(_) => dfa.moves(id),
+                None => Vec::new(),
+            })
+            .collect();
+        let edges = dfa
+            .edges
+            .iter()
+            .map(|e| e.clone().unwrap_or_default())
+            .collect();
+        let mut ahead = vec![(0..moves.len())
+            .map(|id| match dfa.accepts(id) {
+                true => BigUint::one(),
+                false => BigUint::zero(),
+            })
+            .collect::<Vec<_>>()];
+        for len in 1..=max_len {
+            let row = moves
+                .iter()
+                .map(|moves| {
+                    let mut n = BigUint::zero();
+                    for (to, ways) in moves {
+                        n += &(&ahead[len - 1][*to] * &BigUint::from(*ways));
+                    }
+                    n
+                })
+                .collect();
+            ahead.push(row);
+        }
+        Paths { edges, ahead }
+    }
+
+    /// Strings of each length, from zero up to the limit.
+    pub fn counts(&self) -> Vec<BigUint> {
+        self.ahead.iter().map(|row| row[0].clone()).collect()
+    }
+
+    /// Add a random string of `len` bytes to `out`, every one of them
+    /// equally likely. There must be one.
+    pub fn sample(&self, len: usize, rng: &mut Rng, out: &mut Vec<u8>) {
+        let mut at = 0;
+        for left in (0..len).rev() {
+            let edges = &self.edges[at];
+            let pick = choose(
+                rng,
+                edges.iter().map(|(_, to)| self.ahead[left][*to].clone()),
+            );
+            out.push(edges[pick].0);
+            at = edges[pick].1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::compile;
+    use crate::parse;
+
+    fn count_re(re: &str, max_len: usize) -> String {
+        let count = count(&compile(&parse(re.as_bytes()).unwrap()), 0..=max_len);
+        assert!(count.exact, "{}", re);
+        count.strings.to_string()
+    }
+
+    #[test]
+    fn count_a() {
+        assert_eq!(count_re("abc", 5), "1");
+        assert_eq!(count_re("abc", 2), "0");
+        assert_eq!(count_re("[a-c]{2}|x", 2), "10");
+        assert_eq!(count_re("a[^ -~]", 2), "0");
+    }
+
+    #[test]
+    fn count_b() {
+        assert_eq!(count_re("(a|a)", 1), "1");
+        assert_eq!(count_re(r"(\d|[1-9]\d|\d\d)", 2), "110");
+        assert_eq!(count_re("a*a*", 3), "4");
+        assert_eq!(count_re("(a?){3}", 10), "4");
+        assert_eq!(count_re("(a|ab)(c|bcd)(d*)", 4), "5");
+    }
+
+    #[test]
+    fn count_c() {
+        assert_eq!(
+            count_re("^[a-z0-9_-]{6,18}$", 18),
+            "28034099579103634892205993664"
+        );
+        assert_eq!(count_re("^[a-z0-9_-]{6,18}$", 6), "3010936384");
+        assert_eq!(count_re("^#?([a-f0-9]{6}|[a-f0-9]{3})$", 7), "33562624");
+        assert_eq!(count_re(r"\w+", 3), "254079");
+        assert_eq!(count_re("(a{2})*", 1000), "501");
+    }
+
+    #[test]
+    fn count_d() {
+        assert_eq!(count_re(r"([ab])\1", 2), "2");
+        assert_eq!(count_re(r"\2", 2), "1");
+        assert_eq!(count_re(r"(a|b)?\1c", 3), "3");
+        assert_eq!(count_re(r"(a|aa)\1", 4), "2");
+    }
+
+    #[test]
+    fn count_e() {
+        let expr = |re: &str| compile(&parse(re.as_bytes()).unwrap());
+        let lengths = |re: &str, lengths| count(&expr(re), lengths).strings.to_string();
+        assert_eq!(lengths("[ab]{1,4}", 2..=3), "12");
+        assert_eq!(lengths("([ab])\\1{1,3}", 3..=4), "4");
+        #[allow(clippy::reversed_empty_ranges)]
+        let none = lengths("a*", 3..=2);
+        assert_eq!(none, "0");
+        // Too many ways to list, so each reference may match anything its
+        // group can, or nothing: an upper bound
+        let bound = count(&expr(r"([a-z]{4})\1"), 0..=8);
+        assert!(!bound.exact);
+        assert_eq!(
+            bound.strings,
+            BigUint::from(26u64.pow(4) * (26u64.pow(4) + 1))
+        );
+        let bound = count(&expr(r"(a\1)*"), 0..=3);
+        assert!(!bound.exact && bound.strings >= BigUint::from(3));
+    }
+}
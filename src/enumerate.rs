@@ -0,0 +1,515 @@
+use crate::bigint::BigUint;
+use crate::class::CharClass;
+use crate::expr::{Captures, Expr};
+use crate::rank::Counts;
+
+/// Position of the odometer within one expression. A cursor always
+/// describes one complete string the expression can produce.
+enum Cursor<'a> {
+    Empty,
+    Byte(u8),
+    Class(&'a CharClass, usize),
+    Seq(&'a [Expr], Vec<Cursor<'a>>),
+    Alt(&'a [Expr], usize, Box<Cursor<'a>>),
+    /// Inner expression, maximum count and one cursor per repetition.
+    Repeat(&'a Expr, usize, Vec<Cursor<'a>>),
+    Group(usize, Box<Cursor<'a>>),
+    Ref(usize),
+}
+
+impl<'a> Cursor<'a> {
+    /// The first string in enumeration order, or `None` if the expression
+    /// matches nothing.
+    fn first(expr: &'a Expr) -> Option<Self> {
+        match expr {
+            Expr::Empty => Some(Cursor::Empty),
+            Expr::Byte(ch) => Some(Cursor::Byte(*ch)),
+            Expr::Class(set) if set.is_empty() => None,
+            Expr::Class(set) => Some(Cursor::Class(set, 0)),
+            Expr::Seq(exprs) => exprs
+                .iter()
+                .map(Cursor::first)
+                .collect::<Option<Vec<_>>>()
+                .map(|cursors| Cursor::Seq(exprs, cursors)),
+            Expr::Alt(exprs) => Self::branch(exprs, 0),
+            Expr::Repeat(_, min, max) if min > max => None,
+            Expr::Repeat(expr, min, max) => Self::copies(expr, *min, *max),
+            Expr::Group(n, expr) => Self::first(expr).map(|c| Cursor::Group(*n, Box::new(c))),
+            Expr::Ref(n) => Some(Cursor::Ref(*n)),
+        }
+    }
+
+    /// First string of the first branch at or after `from` that matches
+    /// anything.
+    fn branch(exprs: &'a [Expr], from: usize) -> Option<Self> {
+        exprs
+            .iter()
+            .enumerate()
+            .skip(from)
+            .find_map(|(i, expr)| Self::first(expr).map(|c| Cursor::Alt(exprs, i, Box::new(c))))
+    }
+
+    /// First string of exactly `count` repetitions of `expr`.
+    fn copies(expr: &'a Expr, count: usize, max: usize) -> Option<Self> {
+        let mut cursors = Vec::with_capacity(count);
+        for _ in 0..count {
+            cursors.push(Self::first(expr)?);
+        }
+        Some(Cursor::Repeat(expr, max, cursors))
+    }
+
+    /// The string at `index` in enumeration order, which must be below the
+    /// expression's count.
+    fn seek(expr: &'a Expr, mut index: BigUint, counts: &mut Counts) -> Self {
+        match expr {
+            Expr::Empty => Cursor::Empty,
+            Expr::Byte(ch) => Cursor::Byte(*ch),
+            Expr::Class(set) => Cursor::Class(set, index.to_u64().unwrap_or(0) as usize),
+            Expr::Seq(exprs) => Cursor::Seq(exprs, Self::digits(exprs.iter(), index, counts)),
+            Expr::Alt(exprs) => {
+                for (i, expr) in exprs.iter().enumerate() {
+                    let n = counts.of(expr);
+                    if index < n {
+                        return Cursor::Alt(exprs, i, Box::new(Self::seek(expr, index, counts)));
+                    }
+                    index = &index - &n;
+                }
+                unreachable!("index beyond the end of the alternation")
+            }
+            Expr::Repeat(expr, min, max) => {
+                let c = counts.of(expr);
+                let mut count = *min;
+                if c == BigUint::one() {
+                    count += index.to_u64().unwrap_or(0) as usize;
+                    index = BigUint::zero();
+                } else if !c.is_zero() {
+                    let mut n = counts.pow(&c, count);
+                    while index >= n {
+                        index = &index - &n;
+                        n = counts.mul(&n, &c);
+                        count += 1;
+                    }
+                }
+                let copies = Self::digits(std::iter::repeat_n(&**expr, count), index, counts);
+                Cursor::Repeat(expr, *max, copies)
+            }
+            Expr::Group(n, expr) => Cursor::Group(*n, Box::new(Self::seek(expr, index, counts))),
+            Expr::Ref(n) => Cursor::Ref(*n),
+        }
+    }
+
+    /// Cursors for a row of expressions at `index`, with the last one
+    /// turning fastest.
+    fn digits<I>(exprs: I, mut index: BigUint, counts: &mut Counts) -> Vec<Self>
+    where
+        I: DoubleEndedIterator<Item = &'a Expr>,
+    {
+        let mut cursors = Vec::new();
+        for expr in exprs.rev() {
+            let n = counts.of(expr);
+            let (rest, digit) = if index < n {
+                (BigUint::zero(), index)
+            } else {
+                index.div_rem(&n)
+            };
+            cursors.push(Self::seek(expr, digit, counts));
+            index = rest;
+        }
+        cursors.reverse();
+        cursors
+    }
+
+    /// Where this string comes in the enumeration order of `expr`, the
+    /// expression the cursor was made for. The reverse of `seek`.
+    fn index(&self, expr: &Expr, counts: &mut Counts) -> BigUint {
+        match (self, expr) {
+            (Cursor::Class(_, i), _) => BigUint::from(*i as u64),
+            (Cursor::Seq(_, cursors), Expr::Seq(exprs)) => {
+                Self::digit_index(cursors, exprs.iter(), counts)
+            }
+            (Cursor::Alt(_, i, cursor), Expr::Alt(exprs)) => {
+                let before = exprs[..*i]
+                    .iter()
+                    .fold(BigUint::zero(), |n, e| &n + &counts.of(e));
+                &before + &cursor.index(&exprs[*i], counts)
+            }
+            (Cursor::Repeat(_, _, cursors), Expr::Repeat(inner, min, _)) => {
+                let c = counts.of(inner);
+                let shorter = counts.span(&c, *min, cursors.len());
+                let copies = std::iter::repeat_n(&**inner, cursors.len());
+                &shorter + &Self::digit_index(cursors, copies, counts)
+            }
+            (Cursor::Group(_, cursor), Expr::Group(_, inner)) => cursor.index(inner, counts),
+            _ => BigUint::zero(),
+        }
+    }
+
+    /// Position of a row of cursors, the reverse of `digits`.
+    fn digit_index<'e, I>(cursors: &[Self], exprs: I, counts: &mut Counts) -> BigUint
+    where
+        I: Iterator<Item = &'e Expr>,
+    {
+        cursors
+            .iter()
+            .zip(exprs)
+            .fold(BigUint::zero(), |index, (cursor, expr)| {
+                let n = counts.of(expr);
+                &(&index * &n) + &cursor.index(expr, counts)
+            })
+    }
+
+    /// Step to the next string. Returns false once the expression is
+    /// exhausted.
+    fn advance(&mut self) -> bool {
+        match self {
+            Cursor::Empty | Cursor::Byte(_) | Cursor::Ref(_) => false,
+            Cursor::Class(set, i) => {
+                if *i + 1 < set.len() {
+                    *i += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+            Cursor::Seq(exprs, cursors) => {
+                let exprs: &'a [Expr] = exprs;
+                odometer(cursors, |i| &exprs[i])
+            }
+            Cursor::Alt(exprs, i, cursor) => {
+                if cursor.advance() {
+                    return true;
+                }
+                match Self::branch(exprs, *i + 1) {
+                    Some(next) => {
+                        *self = next;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Cursor::Repeat(expr, max, cursors) => {
+                let expr: &'a Expr = expr;
+                if odometer(cursors, |_| expr) {
+                    return true;
+                }
+                if cursors.len() >= *max {
+                    return false;
+                }
+                match Self::copies(expr, cursors.len() + 1, *max) {
+                    Some(next) => {
+                        *self = next;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Cursor::Group(_, cursor) => cursor.advance(),
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>, groups: &mut Captures) {
+        match self {
+            Cursor::Empty => (),
+            Cursor::Byte(ch) => out.push(*ch),
+            Cursor::Class(set, i) => out.extend(set.nth(*i)),
+            Cursor::Seq(_, cursors) | Cursor::Repeat(_, _, cursors) => {
+                cursors.iter().for_each(|c| c.write(out, groups))
+            }
+            Cursor::Alt(_, _, cursor) => cursor.write(out, groups),
+            Cursor::Group(n, cursor) => {
+                let start = out.len();
+                cursor.write(out, groups);
+                groups.set(*n, Some(start..out.len()));
+            }
+            Cursor::Ref(n) => groups.copy(*n, out),
+        }
+    }
+}
+
+/// Advance a row of cursors with the rightmost turning fastest, restarting
+/// every cursor to the right of the one that moved.
+fn odometer<'a, F>(cursors: &mut [Cursor<'a>], expr: F) -> bool
+where
+    F: Fn(usize) -> &'a Expr,
+{
+    for i in (0..cursors.len()).rev() {
+        if cursors[i].advance() {
+            for (j, cursor) in cursors.iter_mut().enumerate().skip(i + 1) {
+                // These produced a string before, so they have a first one
+                if let Some(first) = Cursor::first(expr(j)) {
+                    *cursor = first;
+                }
+            }
+            return true;
+        }
+    }
+    false
+}
+
+/// Lazily yields every string a pattern matches.
+///
+/// Strings come out in a fixed order: the last element of a sequence turns
+/// fastest, alternatives are taken left to right, repeats count up from
+/// their minimum and classes run in byte order. Only the current position
+/// is kept in memory, never the set of strings. Ambiguous patterns such as
+/// `(a|a)` produce a string once for every way it can be matched.
+/// Backreferences repeat what their group produced in the same string, and
+/// nothing if the group didn't take part.
+pub struct Enumerator<'a> {
+    expr: &'a Expr,
+    cursor: Option<Cursor<'a>>,
+    started: bool,
+    captures: Captures,
+    /// Strings still to come, if the enumeration stops short of the end.
+    left: Option<BigUint>,
+}
+
+impl<'a> Enumerator<'a> {
+    pub fn new(expr: &'a Expr) -> Self {
+        Self {
+            expr,
+            cursor: Cursor::first(expr),
+            started: false,
+            captures: Captures::new(expr.groups()),
+            left: None,
+        }
+    }
+
+    /// Start at position `index` of the order instead of the beginning,
+    /// without generating anything before it.
+    pub fn starting_at(expr: &'a Expr, index: &BigUint) -> Self {
+        // Only positions up to the index matter, so counts can stop there
+        let mut counts = Counts::new(Some(index + &BigUint::one()));
+        let cursor = if *index < counts.of(expr) {
+            Some(Cursor::seek(expr, index.clone(), &mut counts))
+        } else {
+            None
+        };
+        Self {
+            expr,
+            cursor,
+            started: false,
+            captures: Captures::new(expr.groups()),
+            left: None,
+        }
+    }
+
+    /// The strings from position `start` of the order up to but not
+    /// including position `end`.
+    pub fn between(expr: &'a Expr, start: &BigUint, end: &BigUint) -> Self {
+        let mut strings = Enumerator::starting_at(expr, start);
+        strings.left = Some(match end > start {
+            true => end - start,
+            false => BigUint::zero(),
+        });
+        strings
+    }
+
+    /// How many strings of the order come before the next one this
+    /// produces, counting those it skipped or won't reach. Starting at
+    /// this position later carries on from here, so it is all a
+    /// checkpoint needs to keep.
+    ///
+    /// This counts the strings of every part of the pattern, so it gives
+    /// `None` for a pattern with unbounded repeats, whose counts are
+    /// astronomical; [`Expr::bounded`] limits them first.
+    pub fn position(&self) -> Option<BigUint> {
+        self.expr.max_len()?;
+        let mut counts = Counts::new(None);
+        Some(match &self.cursor {
+            Some(cursor) if self.started => &cursor.index(self.expr, &mut counts) + &BigUint::one(),
+            Some(cursor) => cursor.index(self.expr, &mut counts),
+            None => counts.of(self.expr),
+        })
+    }
+
+    /// Write the next string into `out` in place of what it held, so that
+    /// one buffer can serve for every string. False once there are no
+    /// more.
+    pub fn next_into(&mut self, out: &mut Vec<u8>) -> bool {
+        let cursor = match self.cursor.as_mut() {
+            Some(cursor) => cursor,
+            None => return false,
+        };
+        // Stopping short keeps the cursor, whose position is then the end
+        if self.left.as_ref().is_some_and(BigUint::is_zero) {
+            return false;
+        }
+        if self.started && !cursor.advance() {
+            self.cursor = None;
+            return false;
+        }
+        if let Some(left) = &mut self.left {
+            left.decrement();
+        }
+        self.started = true;
+        out.clear();
+        self.captures.clear();
+        cursor.write(out, &mut self.captures);
+        true
+    }
+
+    /// The next string together with what each capture group matched in
+    /// it.
+    pub fn next_with_captures(&mut self) -> Option<(Vec<u8>, Captures)> {
+        let mut out = Vec::new();
+        if !self.next_into(&mut out) {
+            return None;
+        }
+        let mut groups = self.captures.clone();
+        groups.set(0, Some(0..out.len()));
+        Some((out, groups))
+    }
+}
+
+impl<'a> Iterator for Enumerator<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.next_with_captures().map(|(s, _)| s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::compile;
+    use crate::parse;
+
+    fn enumerate(re: &str) -> Vec<String> {
+        let expr = compile(&parse(re.as_bytes()).unwrap());
+        Enumerator::new(&expr)
+            .map(|s| String::from_utf8(s).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn enumerate_a() {
+        assert_eq!(enumerate("abc"), vec!["abc"]);
+        assert_eq!(enumerate("[a-c]"), vec!["a", "b", "c"]);
+        assert_eq!(enumerate("[ab]{2}"), vec!["aa", "ab", "ba", "bb"]);
+    }
+
+    #[test]
+    fn enumerate_b() {
+        assert_eq!(enumerate("ab|c|"), vec!["ab", "c", ""]);
+        assert_eq!(enumerate("x(1|23)y"), vec!["x1y", "x23y"]);
+    }
+
+    #[test]
+    fn enumerate_c() {
+        assert_eq!(enumerate("a{0,2}"), vec!["", "a", "aa"]);
+        assert_eq!(enumerate("a?b?"), vec!["", "b", "a", "ab"]);
+        assert_eq!(
+            enumerate("(a|bc){1,2}"),
+            vec!["a", "bc", "aa", "abc", "bca", "bcbc"]
+        );
+    }
+
+    #[test]
+    fn enumerate_d() {
+        assert_eq!(enumerate("((x|y){2}z?){2}").len(), (4 * 2) * (4 * 2));
+    }
+
+    #[test]
+    fn enumerate_e() {
+        let void = Expr::Repeat(Box::new(Expr::Byte(b'a')), 3, 2);
+        assert_eq!(Enumerator::new(&void).next(), None);
+        assert_eq!(enumerate("b[^ -~]?c"), vec!["bc"]);
+        assert_eq!(enumerate("[^ -}]"), vec!["~"]);
+    }
+
+    #[test]
+    fn enumerate_g() {
+        assert_eq!(enumerate(r"([ab])x\1"), vec!["axa", "bxb"]);
+        assert_eq!(enumerate(r"(a|b){2}\1"), vec!["aaa", "abb", "baa", "bbb"]);
+        assert_eq!(enumerate(r"(?:(a)|b)\1"), vec!["aa", "b"]);
+        assert_eq!(enumerate(r"\1(a)"), vec!["a"]);
+        assert_eq!(enumerate(r"<([a-c]{1,2})>\1</\1>").len(), 12);
+    }
+
+    #[test]
+    fn enumerate_h() {
+        let expr = compile(&parse(br"^(\d{5}-\d{4}|\d{5}|\d{9})$").unwrap());
+        let mut strings = Enumerator::new(&expr);
+        let (s, groups) = strings.next_with_captures().unwrap();
+        assert_eq!(s, b"00000-0000");
+        assert_eq!(groups.spans(), &[Some(0..10), Some(0..10)]);
+        let expr = compile(&parse(b"(a)?(b|(c)){1,2}").unwrap());
+        let mut strings = Enumerator::new(&expr);
+        let all = std::iter::from_fn(|| strings.next_with_captures()).collect::<Vec<_>>();
+        assert_eq!(all.len(), 12);
+        assert_eq!(all[0].0, b"b");
+        assert_eq!(all[0].1.spans(), &[Some(0..1), None, Some(0..1), None]);
+        assert_eq!(
+            all[1].1.spans(),
+            &[Some(0..1), None, Some(0..1), Some(0..1)]
+        );
+        // The last iteration wins, while a group it skipped keeps its span
+        assert_eq!(all[4].0, b"cb");
+        assert_eq!(all[4].1.get(2), Some(1..2));
+        assert_eq!(all[4].1.get(3), Some(0..1));
+        assert_eq!(all[6].0, b"ab");
+        assert_eq!(
+            all[6].1.spans(),
+            &[Some(0..2), Some(0..1), Some(1..2), None]
+        );
+    }
+
+    #[test]
+    fn enumerate_f() {
+        let expr = compile(&parse(b"[a-z0-9_-]{6,18}").unwrap());
+        let mut strings = Enumerator::new(&expr);
+        assert_eq!(strings.next(), Some(b"------".to_vec()));
+        assert_eq!(strings.next(), Some(b"-----0".to_vec()));
+        assert_eq!(strings.nth(36), Some(b"----0-".to_vec()));
+        let mut rest = Enumerator::starting_at(&expr, &BigUint::from(39));
+        assert_eq!(rest.next(), strings.next());
+        assert_eq!(rest.next(), strings.next());
+    }
+
+    #[test]
+    fn enumerate_i() {
+        let expr = compile(&parse(b"(x|yz)\\1?").unwrap());
+        let mut strings = Enumerator::new(&expr);
+        let mut s = b"leftover".to_vec();
+        let mut all = Vec::new();
+        while strings.next_into(&mut s) {
+            all.push(String::from_utf8(s.clone()).unwrap());
+        }
+        assert_eq!(all, ["x", "xx", "yz", "yzyz"]);
+        assert!(!strings.next_into(&mut s));
+    }
+
+    #[test]
+    fn enumerate_j() {
+        for re in &[
+            "(a|bc(d|e){0,2}){1,3}\\1",
+            "x[0-9]{0,2}(?:y|z{2,3})",
+            "(a|a)b?",
+            "",
+        ] {
+            let expr = compile(&parse(re.as_bytes()).unwrap());
+            let mut strings = Enumerator::new(&expr);
+            let mut i = 0;
+            loop {
+                let position = strings.position().unwrap();
+                assert_eq!(position, BigUint::from(i), "{}", re);
+                let mut rest = Enumerator::starting_at(&expr, &position);
+                let next = strings.next();
+                assert_eq!(rest.next(), next, "{} at {}", re, i);
+                if next.is_none() {
+                    break;
+                }
+                i += 1;
+            }
+        }
+        let expr = compile(&parse(b"[a-z]{3}").unwrap());
+        let mut strings = Enumerator::between(&expr, &BigUint::from(5), &BigUint::from(9));
+        assert_eq!(strings.position(), Some(BigUint::from(5)));
+        assert_eq!(strings.by_ref().count(), 4);
+        assert_eq!(strings.position(), Some(BigUint::from(9)));
+        let endless = compile(&parse(br"\w+(\.\w+)*").unwrap());
+        assert_eq!(Enumerator::new(&endless).position(), None);
+    }
+}
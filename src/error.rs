@@ -1,4 +1,167 @@
-#[derive(Debug)]
-pub enum ParseError {
-    RegexpParseError,
+use std::fmt;
+use std::ops::Range;
+
+/// What is wrong with a pattern that failed to parse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// A `(` without its `)`, or a `)` without its `(`.
+    UnbalancedParen,
+    /// A `[` without its `]`.
+    UnclosedSet,
+    /// A class range running backwards, like `z-a`.
+    BadRange,
+    /// A `*`, `+` or `?` with nothing before it to repeat.
+    DanglingQuantifier,
+    /// Repeat bounds out of order or too large, like `{3,2}`.
+    BadRepeat,
+    /// A backslash with nothing after it.
+    BadEscape,
+    /// A `(?` followed by something other than a known group kind.
+    BadGroup,
+    /// A byte the parser doesn't accept where it appears.
+    Unexpected,
+    /// A character outside the universe of permitted characters.
+    NotInUniverse,
+    /// Groups nested deeper than the parser allows.
+    TooDeep,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ErrorKind::UnbalancedParen => "unbalanced parenthesis",
+            ErrorKind::UnclosedSet => "unclosed character class",
+            ErrorKind::BadRange => "character range out of order",
+            ErrorKind::DanglingQuantifier => "quantifier with nothing to repeat",
+            ErrorKind::BadRepeat => "invalid repeat bounds",
+            ErrorKind::BadEscape => "incomplete escape",
+            ErrorKind::BadGroup => "unknown group syntax",
+            ErrorKind::Unexpected => "unexpected character",
+            ErrorKind::NotInUniverse => "character not in universe",
+            ErrorKind::TooDeep => "groups nested too deeply",
+        })
+    }
+}
+
+/// A pattern that couldn't be parsed, with the kind of problem and the
+/// byte range of the pattern responsible for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    pub span: Range<usize>,
+    pub pattern: Vec<u8>,
+}
+
+impl ParseError {
+    pub fn new(kind: ErrorKind, span: Range<usize>, pattern: Vec<u8>) -> Self {
+        Self {
+            kind,
+            span,
+            pattern,
+        }
+    }
+
+    /// Byte offset where the problem starts.
+    pub fn offset(&self) -> usize {
+        self.span.start
+    }
+
+    /// The pattern with `^` marks under the offending span on the next
+    /// line.
+    pub fn caret(&self) -> String {
+        let marks = self.span.len().max(1);
+        format!(
+            "{}\n{}{}",
+            String::from_utf8_lossy(&self.pattern),
+            " ".repeat(self.span.start),
+            "^".repeat(marks)
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at byte {}\n{}",
+            self.kind,
+            self.offset(),
+            self.caret()
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A parse failure located by how many bytes of the input were left at
+/// the start and end of the offending span, which `locate` turns into
+/// offsets in the pattern.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Fault {
+    pub kind: ErrorKind,
+    pub from: usize,
+    pub to: usize,
+}
+
+impl Fault {
+    pub fn new(kind: ErrorKind, from: usize, to: usize) -> Self {
+        Self { kind, from, to }
+    }
+
+    /// The error in `pattern`, when the parsed input was `len` bytes long
+    /// with `shift` bytes added in front of the pattern.
+    pub fn locate(self, len: usize, shift: usize, pattern: Vec<u8>) -> ParseError {
+        let at = |rest: usize| (len - rest).saturating_sub(shift).min(pattern.len());
+        let span = at(self.from)..at(self.to);
+        ParseError::new(self.kind, span, pattern)
+    }
+}
+
+/// Why a pattern couldn't be rendered with the requested group values.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderError {
+    /// The pattern has no capture group with this number.
+    NoGroup(usize),
+    /// The value given for this group can't be matched by its sub-pattern.
+    Mismatch(usize),
+    /// No string can use all the values at once, for example because they
+    /// belong to different branches of an alternation.
+    Unsatisfiable,
+    /// The pattern has backreferences, which can't be sampled uniformly.
+    Backreference,
+    /// A value was too long, or took too many steps, to check against its
+    /// group.
+    TooComplex,
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::NoGroup(n) => write!(f, "the pattern has no group {}", n),
+            RenderError::Mismatch(n) => write!(f, "the value for group {} doesn't match it", n),
+            RenderError::Unsatisfiable => write!(f, "no string can use all the group values"),
+            RenderError::Backreference => write!(f, "backreferences can't be sampled uniformly"),
+            RenderError::TooComplex => write!(f, "a group value is too costly to check"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_a() {
+        let e = ParseError::new(ErrorKind::BadRange, 3..6, b"ab[z-a]".to_vec());
+        assert_eq!(e.offset(), 3);
+        assert_eq!(e.caret(), "ab[z-a]\n   ^^^");
+        assert_eq!(
+            e.to_string(),
+            "character range out of order at byte 3\nab[z-a]\n   ^^^"
+        );
+        let e = ParseError::new(ErrorKind::BadEscape, 2..2, b"ab".to_vec());
+        assert_eq!(e.caret(), "ab\n  ^");
+    }
 }
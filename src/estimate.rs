@@ -0,0 +1,165 @@
+use crate::bigint::BigUint;
+use crate::expr::Expr;
+use std::collections::HashMap;
+
+/// How much enumerating a pattern produces, worked out without producing
+/// any of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Estimate {
+    /// Strings in enumeration order, counting every way of producing one.
+    pub strings: BigUint,
+    /// Their total length in bytes.
+    pub bytes: BigUint,
+    /// Length of the longest string, an upper bound as for
+    /// [`Expr::max_len`].
+    pub longest: usize,
+    /// False if the pattern has backreferences, each of which is taken to
+    /// add the average length of its group, so that `bytes` is only
+    /// approximate.
+    pub exact: bool,
+}
+
+/// Strings and their total length for each part of an expression.
+struct Sizes {
+    /// The same for each capture group met so far, for backreferences.
+    groups: HashMap<usize, (BigUint, BigUint)>,
+}
+
+impl Sizes {
+    fn of(&mut self, expr: &Expr) -> (BigUint, BigUint) {
+        match expr {
+            Expr::Empty => (BigUint::one(), BigUint::zero()),
+            Expr::Byte(_) => (BigUint::one(), BigUint::one()),
+            Expr::Class(set) => {
+                let n = BigUint::from(set.len() as u64);
+                (n.clone(), n)
+            }
+            Expr::Seq(exprs) => exprs
+                .iter()
+                .fold((BigUint::one(), BigUint::zero()), |acc, e| {
+                    let next = self.of(e);
+                    then(&acc, &next)
+                }),
+            Expr::Alt(exprs) => exprs
+                .iter()
+                .fold((BigUint::zero(), BigUint::zero()), |acc, e| {
+                    let (n, len) = self.of(e);
+                    (&acc.0 + &n, &acc.1 + &len)
+                }),
+            Expr::Repeat(inner, min, max) => {
+                let one = self.of(inner);
+                if min > max {
+                    return (BigUint::zero(), BigUint::zero());
+                }
+                let (sum, _) = geometric(&one, max - min + 1);
+                then(&geometric(&one, *min).1, &sum)
+            }
+            Expr::Group(n, inner) => {
+                let sizes = self.of(inner);
+                self.groups.insert(*n, sizes.clone());
+                sizes
+            }
+            Expr::Ref(n) => match self.groups.get(n) {
+                Some((strings, bytes)) if !strings.is_zero() => {
+                    (BigUint::one(), bytes.div_rem(strings).0)
+                }
+                _ => (BigUint::one(), BigUint::zero()),
+            },
+        }
+    }
+}
+
+/// Sizes of one part followed by another: every string of the first goes
+/// with every string of the second.
+fn then(a: &(BigUint, BigUint), b: &(BigUint, BigUint)) -> (BigUint, BigUint) {
+    (&a.0 * &b.0, &(&a.1 * &b.0) + &(&b.1 * &a.0))
+}
+
+/// Sizes of `0` up to `n - 1` copies of `c` together, and of `n` copies,
+/// by halving `n` as [`Counts`](crate::rank::Counts) does so that even a
+/// 65536-fold repeat takes a few dozen multiplications.
+fn geometric(c: &(BigUint, BigUint), n: usize) -> ((BigUint, BigUint), (BigUint, BigUint)) {
+    if n == 0 {
+        (
+            (BigUint::zero(), BigUint::zero()),
+            (BigUint::one(), BigUint::zero()),
+        )
+    } else if n % 2 == 1 {
+        let (sum, p) = geometric(c, n - 1);
+        ((&sum.0 + &p.0, &sum.1 + &p.1), then(&p, c))
+    } else {
+        let (sum, p) = geometric(c, n / 2);
+        let next = (&BigUint::one() + &p.0, p.1.clone());
+        (then(&sum, &next), then(&p, &p))
+    }
+}
+
+/// What [`Enumerator`](crate::Enumerator) would produce from `expr`, or
+/// `None` if an unbounded repeat makes it endless. Unbounded repeats can be
+/// limited with [`Expr::bounded`] first.
+pub fn estimate(expr: &Expr) -> Option<Estimate> {
+    let longest = expr.max_len()?;
+    let mut sizes = Sizes {
+        groups: HashMap::new(),
+    };
+    let (strings, bytes) = sizes.of(expr);
+    Some(Estimate {
+        strings,
+        bytes,
+        longest,
+        exact: !expr.has_refs(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enumerate::Enumerator;
+    use crate::expr::compile;
+    use crate::parse;
+
+    fn expr(re: &str) -> Expr {
+        compile(&parse(re.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn estimate_a() {
+        for re in &[
+            "",
+            "abc",
+            "[a-c]{0,3}x?",
+            "(a|bc|)(d|ef){1,3}",
+            "(?:x[0-9]{2}|y){2}",
+            "a[^\\s\\S]",
+            "(a|bc){2,7}",
+            "(?:[ab]?c){0,5}",
+        ] {
+            let expr = expr(re);
+            let all: Vec<_> = Enumerator::new(&expr).collect();
+            let bytes: usize = all.iter().map(Vec::len).sum();
+            let longest = all.iter().map(Vec::len).max().unwrap_or(0);
+            let size = estimate(&expr).unwrap();
+            assert_eq!(size.strings, BigUint::from(all.len() as u64), "{}", re);
+            assert_eq!(size.bytes, BigUint::from(bytes as u64), "{}", re);
+            assert!(size.longest >= longest && size.exact, "{}", re);
+        }
+    }
+
+    #[test]
+    fn estimate_b() {
+        assert_eq!(estimate(&expr("a+")), None);
+        let size = estimate(&expr("[a-z0-9_-]{6,18}")).unwrap();
+        assert_eq!(size.strings.to_string(), "28034099579103634892205993664");
+        assert_eq!(size.longest, 18);
+        let size = estimate(&expr("x{0,65535}y{0,65535}")).unwrap();
+        assert_eq!(
+            (size.strings, size.bytes),
+            (BigUint::from(1 << 32), BigUint::from(65535 << 32))
+        );
+        let refs = estimate(&expr(r"(a|bcd)-\1")).unwrap();
+        assert_eq!(
+            (refs.strings, refs.bytes, refs.exact),
+            (BigUint::from(2), BigUint::from(10), false)
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use crate::alphabet::Alphabet;
+use crate::ast::{GroupKind, Node};
+use crate::class::CharClass;
+use crate::regexgen::INFINITE;
+use std::ops::Range;
+
+/// A parsed pattern lowered into the shape the generators walk: repeats
+/// are explicit nodes and character sets are resolved to the bytes they
+/// can produce.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    /// Matches only the empty string (anchors).
+    Empty,
+    /// A single literal byte.
+    Byte(u8),
+    /// Any one byte of the set, in ascending order. May be empty, in which
+    /// case nothing matches.
+    Class(CharClass),
+    Seq(Vec<Expr>),
+    Alt(Vec<Expr>),
+    /// The inner expression repeated between `min` and `max` times.
+    Repeat(Box<Expr>, usize, usize),
+    /// The inner expression, remembered as capture group `n`, counting
+    /// from 1.
+    Group(usize, Box<Expr>),
+    /// Whatever group `n` produced last, or nothing if it hasn't taken part
+    /// (yet).
+    Ref(usize),
+}
+
+impl Expr {
+    /// True if the expression matches no string at all, not even the
+    /// empty one.
+    pub fn is_void(&self) -> bool {
+        match self {
+            Expr::Empty | Expr::Byte(_) | Expr::Ref(_) => false,
+            Expr::Class(set) => set.is_empty(),
+            Expr::Group(_, expr) => expr.is_void(),
+            Expr::Seq(exprs) => exprs.iter().any(Expr::is_void),
+            Expr::Alt(exprs) => exprs.iter().all(Expr::is_void),
+            Expr::Repeat(expr, min, max) => min > max || (*min > 0 && expr.is_void()),
+        }
+    }
+
+    /// Length of the shortest string matched, or `None` if nothing is.
+    pub fn min_len(&self) -> Option<usize> {
+        match self {
+            Expr::Empty | Expr::Ref(_) => Some(0),
+            Expr::Byte(_) => Some(1),
+            Expr::Group(_, expr) => expr.min_len(),
+            Expr::Class(set) if set.is_empty() => None,
+            Expr::Class(_) => Some(1),
+            Expr::Seq(exprs) => exprs
+                .iter()
+                .try_fold(0usize, |len, e| Some(len.saturating_add(e.min_len()?))),
+            Expr::Alt(exprs) => exprs.iter().filter_map(Expr::min_len).min(),
+            Expr::Repeat(_, min, max) if min > max => None,
+            Expr::Repeat(_, 0, _) => Some(0),
+            Expr::Repeat(expr, min, _) => Some(expr.min_len()?.saturating_mul(*min)),
+        }
+    }
+
+    /// True if a backreference appears anywhere in the expression.
+    pub fn has_refs(&self) -> bool {
+        match self {
+            Expr::Ref(_) => true,
+            Expr::Empty | Expr::Byte(_) | Expr::Class(_) => false,
+            Expr::Seq(exprs) | Expr::Alt(exprs) => exprs.iter().any(Expr::has_refs),
+            Expr::Repeat(expr, _, _) | Expr::Group(_, expr) => expr.has_refs(),
+        }
+    }
+
+    /// The body of capture group `n`, if there is one.
+    pub fn group(&self, n: usize) -> Option<&Expr> {
+        match self {
+            Expr::Group(i, expr) if *i == n => Some(expr),
+            Expr::Empty | Expr::Byte(_) | Expr::Class(_) | Expr::Ref(_) => None,
+            Expr::Seq(exprs) | Expr::Alt(exprs) => exprs.iter().find_map(|e| e.group(n)),
+            Expr::Repeat(expr, _, _) | Expr::Group(_, expr) => expr.group(n),
+        }
+    }
+
+    /// Longest string matched, or `None` if an unbounded repeat means
+    /// there is no limit. Parts that match nothing still count, so this is
+    /// an upper bound. A group referring to itself can double in length
+    /// each time it repeats, so it too is taken to have no limit.
+    pub fn max_len(&self) -> Option<usize> {
+        self.longest(self, &mut Vec::new())
+    }
+
+    /// `open` holds the groups whose references are being followed.
+    fn longest(&self, root: &Expr, open: &mut Vec<usize>) -> Option<usize> {
+        match self {
+            Expr::Empty => Some(0),
+            Expr::Byte(_) | Expr::Class(_) => Some(1),
+            Expr::Group(_, expr) => expr.longest(root, open),
+            Expr::Ref(n) if open.contains(n) => None,
+            Expr::Ref(n) => match root.group(*n) {
+                Some(e) => {
+                    open.push(*n);
+                    let len = e.longest(root, open);
+                    open.pop();
+                    len
+                }
+                None => Some(0),
+            },
+            Expr::Seq(exprs) => exprs.iter().try_fold(0usize, |len, e| {
+                Some(len.saturating_add(e.longest(root, open)?))
+            }),
+            Expr::Alt(exprs) => exprs
+                .iter()
+                .try_fold(0, |len, e| Some(len.max(e.longest(root, open)?))),
+            Expr::Repeat(_, _, max) if *max >= INFINITE as usize => None,
+            Expr::Repeat(expr, _, max) => Some(expr.longest(root, open)?.saturating_mul(*max)),
+        }
+    }
+
+    /// The same expression with each unbounded repeat (`*`, `+`, `{n,}`)
+    /// taking at most `extra` iterations beyond its minimum, so that there
+    /// are finitely many strings.
+    pub fn bounded(&self, extra: usize) -> Expr {
+        match self {
+            Expr::Empty | Expr::Byte(_) | Expr::Class(_) | Expr::Ref(_) => self.clone(),
+            Expr::Seq(exprs) => Expr::Seq(exprs.iter().map(|e| e.bounded(extra)).collect()),
+            Expr::Alt(exprs) => Expr::Alt(exprs.iter().map(|e| e.bounded(extra)).collect()),
+            Expr::Group(n, expr) => Expr::Group(*n, Box::new(expr.bounded(extra))),
+            Expr::Repeat(expr, min, max) => {
+                let max = match *max >= INFINITE as usize {
+                    true => min.saturating_add(extra),
+                    false => *max,
+                };
+                Expr::Repeat(Box::new(expr.bounded(extra)), *min, max)
+            }
+        }
+    }
+
+    /// Number of capture groups, which is also the highest group number.
+    pub fn groups(&self) -> usize {
+        match self {
+            Expr::Group(n, expr) => (*n).max(expr.groups()),
+            Expr::Empty | Expr::Byte(_) | Expr::Class(_) | Expr::Ref(_) => 0,
+            Expr::Seq(exprs) | Expr::Alt(exprs) => {
+                exprs.iter().map(Expr::groups).max().unwrap_or(0)
+            }
+            Expr::Repeat(expr, _, _) => expr.groups(),
+        }
+    }
+}
+
+/// Where each capture group last matched in a generated string, indexed by
+/// group number. Group 0 is the whole string, and groups that took no part
+/// are `None`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Captures(Vec<Option<Range<usize>>>);
+
+impl Captures {
+    /// Room for groups 0 up to `groups`, none of them set.
+    pub fn new(groups: usize) -> Self {
+        Captures(vec![None; groups + 1])
+    }
+
+    /// Unset every group.
+    pub fn clear(&mut self) {
+        self.0.iter_mut().for_each(|span| *span = None);
+    }
+
+    pub fn spans(&self) -> &[Option<Range<usize>>] {
+        &self.0
+    }
+
+    pub fn get(&self, group: usize) -> Option<Range<usize>> {
+        self.0.get(group).cloned().flatten()
+    }
+
+    pub fn set(&mut self, group: usize, span: Option<Range<usize>>) {
+        if self.0.len() <= group {
+            self.0.resize(group + 1, None);
+        }
+        self.0[group] = span;
+    }
+
+    /// Append another copy of what `group` produced in `out`, if anything.
+    pub fn copy(&self, group: usize, out: &mut Vec<u8>) {
+        if let Some(span) = self.get(group) {
+            out.extend_from_within(span);
+        }
+    }
+}
+
+fn all(nodes: &[Node], alphabet: &Alphabet) -> Vec<Expr> {
+    nodes
+        .iter()
+        .map(|node| compile_with(node, alphabet))
+        .collect()
+}
+
+/// Lower a parsed pattern into the expression the generators walk, over
+/// the default [`Alphabet`] of printable ASCII.
+pub fn compile(node: &Node) -> Expr {
+    compile_with(node, &Alphabet::default())
+}
+
+/// Lower a parsed pattern into the expression the generators walk, with
+/// `.` and negated sets choosing from `alphabet`.
+pub fn compile_with(node: &Node, alphabet: &Alphabet) -> Expr {
+    let compile = |node| compile_with(node, alphabet);
+    match node {
+        Node::Empty | Node::Start | Node::End => Expr::Empty,
+        Node::Any => Expr::Class(alphabet.dot()),
+        Node::Text(bytes) => {
+            let byte = |&ch: &u8| match alphabet.universe().contains(ch) {
+                true => Expr::Byte(ch),
+                false => Expr::Class(CharClass::new()),
+            };
+            match &bytes[..] {
+                [ch] => byte(ch),
+                _ => Expr::Seq(bytes.iter().map(byte).collect()),
+            }
+        }
+        Node::Charset {
+            class,
+            negated: false,
+        } => Expr::Class(class.intersection(alphabet.universe())),
+        Node::Charset {
+            class,
+            negated: true,
+        } => Expr::Class(alphabet.except(*class)),
+        Node::Seq(nodes) => Expr::Seq(all(nodes, alphabet)),
+        Node::Select(nodes) => {
+            let mut branches = all(nodes, alphabet);
+            if !branches.iter().all(Expr::is_void) {
+                branches.retain(|e| !e.is_void());
+            }
+            Expr::Alt(branches)
+        }
+        Node::Repeat { node, min, max } => {
+            let max = max.unwrap_or(INFINITE as usize);
+            Expr::Repeat(Box::new(compile(node)), *min, max)
+        }
+        Node::Group(node, GroupKind::Capture(n)) => Expr::Group(*n, Box::new(compile(node))),
+        // Assertions match no text of their own, and generation doesn't
+        // check them
+        Node::Group(_, GroupKind::Lookahead { .. })
+        | Node::Group(_, GroupKind::Lookbehind { .. }) => Expr::Empty,
+        Node::Group(node, _) => compile(node),
+        Node::Ref(n) => Expr::Ref(*n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn compile_a() {
+        assert_eq!(
+            compile(&parse(b"[cab|a]{2,3}").unwrap()),
+            Expr::Seq(vec![
+                Expr::Empty,
+                Expr::Repeat(Box::new(Expr::Class(CharClass::range(b'a', b'c'))), 2, 3),
+                Expr::Empty
+            ])
+        );
+    }
+
+    #[test]
+    fn compile_b() {
+        assert_eq!(
+            compile(&parse(b"[^ -}]").unwrap()),
+            Expr::Seq(vec![
+                Expr::Empty,
+                Expr::Class(CharClass::from_bytes(b"~")),
+                Expr::Empty
+            ])
+        );
+    }
+
+    #[test]
+    fn compile_c() {
+        let expr = compile(&parse(br"(a(?:b)(c))\2").unwrap());
+        let c = Expr::Seq(vec![Expr::Byte(b'c')]);
+        assert_eq!(expr.group(2), Some(&c));
+        assert_eq!(expr.group(1).and_then(|g| g.group(2)), Some(&c));
+        assert_eq!(expr.group(3), None);
+        assert_eq!(expr.groups(), 2);
+        assert!(expr.has_refs());
+        assert_eq!(
+            compile(&parse(b"(?!x)").unwrap()),
+            Expr::Seq(vec![Expr::Empty, Expr::Empty, Expr::Empty])
+        );
+    }
+
+    #[test]
+    fn compile_d() {
+        let class = |re: &[u8]| match compile(&parse(re).unwrap()) {
+            Expr::Seq(exprs) => exprs[1].clone(),
+            expr => expr,
+        };
+        let printable = CharClass::range(b' ', b'~');
+        let lower = CharClass::range(b'a', b'z').union(CharClass::from_bytes(b"<"));
+        assert_eq!(
+            class(b"[^<a-z]"),
+            Expr::Class(printable.intersection(lower.complement()))
+        );
+        // Everything but a non-digit, 1 or 2
+        assert_eq!(
+            class(br"[^\D12]"),
+            Expr::Class(CharClass::from_bytes(b"03456789"))
+        );
+    }
+
+    #[test]
+    fn compile_e() {
+        let class =
+            |re: &[u8], alphabet: Alphabet| match compile_with(&parse(re).unwrap(), &alphabet) {
+                Expr::Seq(exprs) => exprs[1].clone(),
+                expr => expr,
+            };
+        let printable = CharClass::range(b' ', b'~');
+        assert_eq!(class(b".", Alphabet::default()), Expr::Class(printable));
+        let ascii = Alphabet::ascii();
+        assert_eq!(
+            class(b".", ascii),
+            Expr::Class(printable.union(CharClass::from_bytes(b"\t\x0b\x0c\r")))
+        );
+        assert_eq!(
+            class(b".", ascii.dot_newline(true)),
+            Expr::Class(ascii.class())
+        );
+        assert_eq!(class(b"[^\n]", ascii.dot_newline(true)), class(b".", ascii));
+        let abc = Alphabet::custom(CharClass::range(b'a', b'c'));
+        assert_eq!(class(br"\W", abc), Expr::Class(CharClass::new()));
+        assert_eq!(
+            class(br"[^b]", abc),
+            Expr::Class(CharClass::from_bytes(b"ac"))
+        );
+        assert_eq!(class(br"[\Db]", abc), class(b"[abc]", abc));
+        assert_eq!(class(br"[\s\S]", abc), class(b".", abc));
+        assert_eq!(
+            class(br"\S", Alphabet::bytes()),
+            Expr::Class(CharClass::from_bytes(b"\t ").complement())
+        );
+    }
+
+    #[test]
+    fn compile_f() {
+        let abc = Alphabet::default().within(CharClass::range(b'a', b'c'));
+        let compile_in = |re: &[u8]| compile_with(&parse(re).unwrap(), &abc);
+        assert_eq!(
+            compile_in(b"[^b]|[x-z]|d+|[a-z]"),
+            Expr::Alt(vec![
+                Expr::Seq(vec![Expr::Empty, Expr::Class(CharClass::from_bytes(b"ac"))]),
+                Expr::Seq(vec![Expr::Class(CharClass::range(b'a', b'c')), Expr::Empty]),
+            ])
+        );
+        assert!(compile_in(b"x|y").is_void());
+        assert_eq!(
+            crate::count(&compile_in(b".{2}"), 0..=2)
+                .strings
+                .to_string(),
+            "9"
+        );
+    }
+
+    #[test]
+    fn bounded_a() {
+        let expr = compile(&parse(br"(ab|c)*x{2,}\1?").unwrap());
+        assert_eq!(expr.max_len(), None);
+        let bounded = expr.bounded(3);
+        assert_eq!(bounded.max_len(), Some(6 + 5 + 2));
+        assert_eq!(crate::count(&bounded, 0..=100).strings.to_string(), "116");
+        assert_eq!(
+            compile(&parse(b"a{2,4}").unwrap()).bounded(0).max_len(),
+            Some(4)
+        );
+        assert_eq!(compile(&parse(br"(a\1){0,3}").unwrap()).max_len(), None);
+    }
+}
@@ -0,0 +1,110 @@
+//! Generate the strings a regular expression matches.
+//!
+//! [`parse`] turns a pattern into an [`ast::Node`] tree, and [`compile`]
+//! lowers that into the [`Expr`] every generator works on: [`Enumerator`]
+//! lists the strings in order, or [`Parallel`] on several threads, [`nth`]
+//! and [`rank`] index into that order, [`Sampler`] draws random ones,
+//! [`count`] counts them and [`estimate`] sizes up an enumeration.
+
+mod alphabet;
+pub mod ast;
+mod batch;
+mod bigint;
+mod class;
+mod count;
+mod enumerate;
+mod error;
+mod estimate;
+mod expr;
+mod matcher;
+mod normalize;
+mod output;
+mod parallel;
+// Earlier parsers, superseded by `regexgen`
+#[allow(dead_code)]
+mod parse_regex;
+#[allow(dead_code)]
+mod parse_regexp;
+mod print;
+mod rank;
+mod regexgen;
+mod rng;
+mod sample;
+mod uniform;
+
+pub use alphabet::Alphabet;
+pub use batch::{patterns, Entry};
+pub use bigint::BigUint;
+pub use class::CharClass;
+pub use count::{count, Count};
+pub use enumerate::Enumerator;
+pub use error::{ErrorKind, ParseError, RenderError};
+pub use estimate::{estimate, Estimate};
+pub use expr::{compile, compile_with, Captures, Expr};
+pub use normalize::normalize;
+pub use output::{Encoder, Encoding, RecordWriter};
+pub use parallel::Parallel;
+pub use print::print;
+pub use rank::{nth, rank, shard};
+pub use rng::Rng;
+pub use sample::{Length, Sampler, Unbounded};
+
+/// Parse a pattern.
+///
+/// Patterns are anchored at both ends: a leading `^` and a trailing `$`
+/// are added to the tree when the pattern doesn't spell them out.
+pub fn parse(pattern: &[u8]) -> Result<ast::Node, ParseError> {
+    regexgen::parse(pattern.to_vec()).map(|node| node.lower())
+}
+
+/// Like [`parse`], but for a pattern that may only name bytes in
+/// `universe`, failing with [`ErrorKind::NotInUniverse`] otherwise. Ranges
+/// and classes inside sets are cut down to `universe` instead, and
+/// [`Alphabet::within`] does the same for generation.
+pub fn parse_in(pattern: &[u8], universe: &CharClass) -> Result<ast::Node, ParseError> {
+    regexgen::parse_in(pattern.to_vec(), universe).map(|node| node.lower())
+}
+
+/// The patterns of the `parse_regexp` tests, less `parse_32`, whose
+/// class never closes.
+#[cfg(test)]
+const CORPUS: &[&str] = &[
+    r"[A-Z]{1,2}",
+    r"[\w]{1,2}",
+    r"\w+([-+.]\w+)*@\w+([-.]\w+)*\.\w+([-.]\w+)*",
+    r"^[\w\.\']{2,}([\s][\w\.\']{2,})+$",
+    r"^([a-z][a-z0-9\-]+(\.|\-*\.))+[a-z]{2,6}$",
+    r"^(\d|[1-9]\d|1\d\d|2[0-4]\d|25[0-5])\.(\d|[1-9]\d|1\d\d|2[0-4]\d|25[0-5]){3}$",
+    r"^[\_]*([a-z0-9]+(\.|\_*)?)+@([a-z][a-z0-9\-]+(\.|\-*\.))+[a-z]{2,6}$",
+    r"^([1-9]|0[1-9]|[12][0-9]|3[01])\D([1-9]|0[1-9]|1[012])\D(19[0-9][0-9]|20[0-9][0-9])$",
+    r"^-?([1-9]\d*\.\d*|0\.\d*[1-9]\d*|0?\.0+|0)$",
+    r"^[1-9]\d*\.\d*|0\.\d*[1-9]\d*|0?\.0+|0$",
+    r"^(-([1-9]\d*\.\d*|0\.\d*[1-9]\d*))|0?\.0+|0$",
+    r"^<([a-z]+)[^<a-z]+?(?:>(.*)<\/\1>)$",
+    r"^<([a-z]+)[^<a-z]+?(?:>(.*)<\/\1>|\s+\/>)$",
+    r"^<([a-z]+)[^<a-z]+?(?:>(.*)<\/\\>|\s+\/>)$",
+    r"(|)((()|(|))|())",
+    r"(c((w)|e+)\?)*",
+    r"(?:c(?:(?:w)|e+))*",
+    r"a{2,2}|(c((w)|e+)\?)*[^^bd?\]]?$",
+    r"^(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)$",
+    r"^(https?:\/\/)?([\da-z\.-]+)\.([a-z\.]{2,6})([\/\w \.-]*)*\/?$",
+    r"[^\D12]+",
+    r"^([a-z0-9_\.-]+)@([\da-z\.-]+)\.([a-z\.]{2,6})$",
+    r"^#?([a-f0-9]{6}|[a-f0-9]{3})$",
+    r"^[a-z0-9_-]{6,18}$",
+    r"^\w[-\w\d]\d{8}$",
+    r".?[\W]{3,16}$",
+    r"^.?[.-.d \-~a-z0-9_-]{3,16}$",
+    r"\(\(\(ab\)*c\)*d\)\(ef\)*\(gh\)\{2\}\(ij\)*\(kl\)*\(mn\)*\(op\)*\(qr\)*",
+    r"(c((a)|e+)\?)*[^^bd?\]]?$",
+    r"^#?([a-f0-9]{6}|[a-f0-9]{3})$",
+    r"(a)\1+?\\+?",
+    r"^\D?(\d{3})\D?\D?(\d{3})\D?(\d{4})$",
+    r"^(http|https|ftp)\://[a-zA-Z0-9\-\.]+\.[a-zA-Z]{2,3}(:[a-zA-Z0-9]*)?/?([a-zA-Z0-9\-\._\?\,\'/\\\+&%\$#\=~])*$",
+    r"^(\d{5}-\d{4}|\d{5}|\d{9})$|^([a-zA-Z]\d[a-zA-Z] \d[a-zA-Z]\d)$",
+    r"\w+([-+.]\w+)*@\w+([-.]\w+)*\.\w+([-.]\w+)*([,;]\s*\w+([-+.]\w+)*@\w+([-.]\w+)*\.\w+([-.]\w+)*)*",
+    r"((?:(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.){3}(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]\d|[1-9]))",
+    r"(25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)(?:\.\1){3}",
+    r"(a-z|A-Z|:;+)[cd]{2}\1",
+];
@@ -1,12 +1,1039 @@
-mod error;
-//mod regexgen;
-mod parse_regex;
+use reggen::{
+    ast, compile_with, count, estimate, patterns, shard, Alphabet, BigUint, Encoder, Encoding,
+    Enumerator, Expr, Parallel, ParseError, RecordWriter, Sampler,
+};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-//use crate::regldg::*;
+const USAGE: &str = "\
+usage: reggen [options] [pattern]
+       reggen [options] --file FILE
 
-fn main() -> Result<(), error::ParseError> {
-    //let _v = regexgen::parse(b"dfsddf".to_vec())?;
-    let regex = b"(a-z|A-Z|:;+)[cd]{2}\\1";
-    let _v = parse_regex::parse(&regex.to_vec());
+Generate strings matching a pattern, read from standard input when it
+isn't given. With --file, do so for every pattern in FILE, one per line,
+skipping empty lines and lines starting with #, and put the line number
+and a tab before each string written.
+
+options:
+  -m, --mode MODE        enumerate (default), random, count for the number
+                         of distinct strings, or estimate for how many
+                         strings enumerating gives, their total size and
+                         the longest. A count is given as 'at most N'
+                         when backreferences leave it an upper bound, and
+                         in JSON as an object holding count and exact
+  -n, --count N          strings to write; all of them when enumerating,
+                         10 when random
+  -s, --seed N           seed for random mode, from the clock by default
+  -r, --max-repeat N     iterations beyond the minimum for *, + and {n,}
+                         (default 8); a count with --max-length takes
+                         as many as the length allows instead
+      --min-length N     only strings of at least N bytes
+      --max-length N     only strings of at most N bytes
+  -u, --unique           never write the same string twice
+  -j, --threads N        enumerate on N threads (default 1)
+      --ordered          with --threads, keep the order of one thread
+      --shard K/N        enumerate only the Kth of N slices of equal size,
+                         from 1/N to N/N, which between them hold every
+                         string
+      --max-lines N      stop after writing N strings in all
+      --max-bytes N      stop before writing more than N bytes in all
+      --threshold SIZE   refuse to enumerate when the output would come to
+                         more than SIZE bytes, with K, M, G or T for
+                         powers of 1024 (default 1G)
+      --force            enumerate whatever the size
+      --checkpoint FILE  when enumerating, keep track of how far it got in
+                         FILE, and carry on from there if FILE exists. FILE
+                         is brought up to date every 65536 strings and at
+                         the end. The output file, if any, is then cut back
+                         to what it held at the last update and added to,
+                         so it carries on exactly after the last string;
+                         on standard output a run killed between updates
+                         writes again what it wrote since
+  -a, --alphabet SET     what ., negated sets and \\D, \\W, \\S produce:
+                         printable (default), ascii, bytes, or set members
+                         such as 'a-z0-9'
+      --dot-newline      let . produce a newline
+  -e, --encoding ENC     lines (default), nul, json, jsonl, csv, or hex for
+                         lines with anything unprintable escaped
+  -f, --file FILE        read patterns from FILE
+  -o, --output FILE      write to FILE instead of standard output
+  -h, --help             show this help
+
+exit status: 0 on success, 1 on a runtime error, 2 on bad usage and 3
+when the pattern doesn't parse. With --file, every pattern is tried and
+the status is 3 if any didn't parse, or else 1 if any failed otherwise.
+Output closed early, as by head, is not an error";
+
+/// Random mode gives up after this many strings in a row are rejected as
+/// too short, too long or already written.
+const MAX_REJECTS: usize = 1000;
+
+/// Bytes of output held back before standard output is written to.
+const OUT_BUFFER: usize = 1 << 16;
+
+/// Output, in bytes, above which enumerating needs `--force`.
+const THRESHOLD: u64 = 1 << 30;
+
+/// Strings enumerated between updates to a checkpoint file.
+const CHECKPOINT_EVERY: u64 = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Enumerate,
+    Random,
+    Count,
+    Estimate,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    pattern: Option<Vec<u8>>,
+    file: Option<String>,
+    mode: Mode,
+    count: Option<u64>,
+    seed: Option<u64>,
+    max_repeat: usize,
+    min_len: usize,
+    max_len: Option<usize>,
+    unique: bool,
+    threads: usize,
+    ordered: bool,
+    /// Slice to enumerate, counting from 0, and how many slices there are.
+    shard: Option<(u64, u64)>,
+    max_lines: Option<u64>,
+    max_bytes: Option<u64>,
+    checkpoint: Option<String>,
+    threshold: u64,
+    force: bool,
+    alphabet: Alphabet,
+    encoding: Encoding,
+    output: Option<String>,
+    help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            pattern: None,
+            file: None,
+            mode: Mode::Enumerate,
+            count: None,
+            seed: None,
+            max_repeat: 8,
+            min_len: 0,
+            max_len: None,
+            unique: false,
+            threads: 1,
+            ordered: false,
+            shard: None,
+            max_lines: None,
+            max_bytes: None,
+            checkpoint: None,
+            threshold: THRESHOLD,
+            force: false,
+            alphabet: Alphabet::default(),
+            encoding: Encoding::Lines,
+            output: None,
+            help: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Error {
+    Usage(String),
+    Parse(ParseError),
+    Runtime(String),
+    Io(io::Error),
+    /// Patterns of a pattern file that failed, and whether any of them
+    /// didn't parse.
+    Batch(usize, bool),
+}
+
+impl Error {
+    fn code(&self) -> i32 {
+        match self {
+            Error::Runtime(_) | Error::Io(_) | Error::Batch(_, false) => 1,
+            Error::Usage(_) => 2,
+            Error::Parse(_) | Error::Batch(_, true) => 3,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usage(msg) => write!(f, "{}\n\n{}", msg, USAGE),
+            Error::Parse(e) => e.fmt(f),
+            Error::Runtime(msg) => f.write_str(msg),
+            Error::Io(e) => e.fmt(f),
+            Error::Batch(1, _) => f.write_str("1 pattern failed"),
+            Error::Batch(n, _) => write!(f, "{} patterns failed", n),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::Usage(format!("{} expects a number, not '{}'", name, value)))
+}
+
+/// A number of bytes, in units of 1024 with a `K` after it, 1024 K with an
+/// `M`, and so on.
+fn size(name: &str, value: &str) -> Result<u64, Error> {
+    let (digits, shift) = match value.chars().last().and_then(|unit| "KMGT".find(unit)) {
+        Some(i) => (&value[..value.len() - 1], 10 * (i + 1)),
+        None => (value, 0),
+    };
+    number::<u64>(name, digits)?
+        .checked_mul(1 << shift)
+        .ok_or_else(|| Error::Usage(format!("{} is too large: '{}'", name, value)))
+}
+
+/// An alphabet by name, or else one holding the members of a set written
+/// as between `[` and `]`.
+fn alphabet(value: &str) -> Result<Alphabet, Error> {
+    match value {
+        "printable" => return Ok(Alphabet::printable()),
+        "ascii" => return Ok(Alphabet::ascii()),
+        "bytes" => return Ok(Alphabet::bytes()),
+        _ => (),
+    }
+    let set = format!("[{}]", value);
+    match reggen::parse(set.as_bytes()) {
+        Ok(ast::Node::Seq(nodes)) => match &nodes[..] {
+            [ast::Node::Start, ast::Node::Charset {
+                class,
+                negated: false,
+            }, ast::Node::End] => Ok(Alphabet::custom(*class)),
+            _ => Err(Error::Usage(format!("bad alphabet '{}'", value))),
+        },
+        _ => Err(Error::Usage(format!("bad alphabet '{}'", value))),
+    }
+}
+
+/// A slice `K/N`, from 1 up to N, as a count from 0 and N.
+fn shard_of(value: &str) -> Result<(u64, u64), Error> {
+    let bad = || {
+        Error::Usage(format!(
+            "--shard expects K/N with K from 1 to N, not '{}'",
+            value
+        ))
+    };
+    let (k, n) = value.split_once('/').ok_or_else(bad)?;
+    match (k.parse::<u64>(), n.parse::<u64>()) {
+        (Ok(k), Ok(n)) if (1..=n).contains(&k) => Ok((k - 1, n)),
+        _ => Err(bad()),
+    }
+}
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, Error> {
+    let mut options = Options::default();
+    let mut dot_newline = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // `--name=value` is the same as `--name value`
+        let (name, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => {
+                (arg[..i].to_string(), Some(arg[i + 1..].to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| Error::Usage(format!("{} expects a value", name)))
+        };
+        match name.as_str() {
+            "-m" | "--mode" => {
+                options.mode = match value()?.as_str() {
+                    "enumerate" => Mode::Enumerate,
+                    "random" => Mode::Random,
+                    "count" => Mode::Count,
+                    "estimate" => Mode::Estimate,
+                    mode => return Err(Error::Usage(format!("unknown mode '{}'", mode))),
+                }
+            }
+            "-n" | "--count" => options.count = Some(number(&name, &value()?)?),
+            "-s" | "--seed" => options.seed = Some(number(&name, &value()?)?),
+            "-r" | "--max-repeat" => options.max_repeat = number(&name, &value()?)?,
+            "--min-length" => options.min_len = number(&name, &value()?)?,
+            "--max-length" => options.max_len = Some(number(&name, &value()?)?),
+            "-u" | "--unique" => options.unique = true,
+            "-j" | "--threads" => options.threads = number(&name, &value()?)?,
+            "--ordered" => options.ordered = true,
+            "--shard" => options.shard = Some(shard_of(&value()?)?),
+            "--max-lines" => options.max_lines = Some(number(&name, &value()?)?),
+            "--max-bytes" => options.max_bytes = Some(number(&name, &value()?)?),
+            "--checkpoint" => options.checkpoint = Some(value()?),
+            "--threshold" => options.threshold = size(&name, &value()?)?,
+            "--force" => options.force = true,
+            "-a" | "--alphabet" => options.alphabet = alphabet(&value()?)?,
+            "--dot-newline" => dot_newline = true,
+            "-e" | "--encoding" => {
+                options.encoding = match value()?.as_str() {
+                    "lines" => Encoding::Lines,
+                    "nul" => Encoding::Nul,
+                    "json" => Encoding::Json,
+                    "jsonl" => Encoding::JsonLines,
+                    "csv" => Encoding::Csv,
+                    "hex" => Encoding::Hex,
+                    encoding => {
+                        return Err(Error::Usage(format!("unknown encoding '{}'", encoding)))
+                    }
+                }
+            }
+            "-f" | "--file" => options.file = Some(value()?),
+            "-o" | "--output" => options.output = Some(value()?),
+            "-h" | "--help" => options.help = true,
+            "--" => {
+                options.pattern = args.next().map(String::into_bytes);
+                break;
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(Error::Usage(format!("unknown option '{}'", arg)))
+            }
+            _ if options.pattern.is_some() => {
+                return Err(Error::Usage("more than one pattern given".to_string()))
+            }
+            _ => options.pattern = Some(arg.into_bytes()),
+        }
+    }
+    options.alphabet = options.alphabet.dot_newline(dot_newline);
+    if options.pattern.is_some() && options.file.is_some() {
+        return Err(Error::Usage("both a pattern and --file given".to_string()));
+    }
+    if options.mode != Mode::Enumerate {
+        let given = [
+            ("--shard", options.shard.is_some()),
+            ("--threads", options.threads > 1),
+            ("--ordered", options.ordered),
+        ];
+        if let Some((name, _)) = given.iter().find(|(_, given)| *given) {
+            return Err(Error::Usage(format!(
+                "{} only applies when enumerating",
+                name
+            )));
+        }
+    }
+    if options.checkpoint.is_some() {
+        let clash = match () {
+            _ if options.mode != Mode::Enumerate => Some("only applies when enumerating"),
+            _ if options.file.is_some() => Some("can't be used with --file"),
+            _ if options.unique => Some("can't keep track of --unique"),
+            _ if options.threads > 1 && !options.ordered => Some("needs --ordered with --threads"),
+            _ => None,
+        };
+        if let Some(clash) = clash {
+            return Err(Error::Usage(format!("--checkpoint {}", clash)));
+        }
+    }
+    if options.threads == 0 {
+        return Err(Error::Usage("--threads must be at least 1".to_string()));
+    }
+    if options.max_len.is_some_and(|max| max < options.min_len) {
+        return Err(Error::Usage(
+            "--max-length is below --min-length".to_string(),
+        ));
+    }
+    Ok(options)
+}
+
+/// How far an enumeration with `--checkpoint` has got.
+#[derive(Debug, PartialEq)]
+struct Checkpoint {
+    path: String,
+    /// The pattern and options it was made with.
+    setup: String,
+    /// Position in enumeration order of the next string to look at, or
+    /// `None` before the first run.
+    position: Option<BigUint>,
+    /// Strings written by every run so far.
+    written: u64,
+    /// The output file, if there is one, and its size at the last update.
+    output: Option<String>,
+    bytes: u64,
+}
+
+impl Checkpoint {
+    /// The checkpoint in `path` if there is one, which must have been made
+    /// with `setup`, or else a new one, for output to `output`.
+    fn open(path: &str, setup: String, output: Option<&str>) -> Result<Self, Error> {
+        let mut checkpoint = Checkpoint {
+            path: path.to_string(),
+            setup,
+            position: None,
+            written: 0,
+            output: output.map(String::from),
+            bytes: 0,
+        };
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(checkpoint),
+            Err(e) => return Err(Error::Runtime(format!("{}: {}", path, e))),
+        };
+        let bad = |what: &str| Error::Runtime(format!("{}: {}", path, what));
+        let mut lines = text.lines();
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name)?.strip_prefix(' '))
+                .ok_or_else(|| bad("not a reggen checkpoint"))
+        };
+        field("reggen")?;
+        if field("setup")? != checkpoint.setup {
+            return Err(bad(
+                "made for another pattern or other options, not resuming",
+            ));
+        }
+        let position = field("position")?;
+        let written = field("written")?;
+        let bytes = field("bytes")?;
+        checkpoint.position = Some(decimal(position).ok_or_else(|| bad("bad position"))?);
+        checkpoint.written = written.parse().map_err(|_| bad("bad count"))?;
+        checkpoint.bytes = bytes.parse().map_err(|_| bad("bad size"))?;
+        Ok(checkpoint)
+    }
+
+    /// Cut the output file back to its size at the last update, dropping
+    /// whatever a run killed since then wrote, so that resuming carries on
+    /// right after the last string the checkpoint counts.
+    fn rewind(&self) -> Result<(), Error> {
+        match (&self.output, &self.position) {
+            (Some(output), Some(_)) => File::options()
+                .write(true)
+                .open(output)
+                .and_then(|file| file.set_len(self.bytes))
+                .map_err(|e| Error::Runtime(format!("{}: {}", output, e))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record `position` and `written`, and the size of the output file,
+    /// which must be flushed. The checkpoint file is replaced whole so that
+    /// it is never left half written.
+    fn save(&mut self, position: BigUint, written: u64) -> Result<(), Error> {
+        let bytes = match &self.output {
+            Some(output) => std::fs::metadata(output)
+                .map_err(|e| Error::Runtime(format!("{}: {}", output, e)))?
+                .len(),
+            None => 0,
+        };
+        let text = format!(
+            "reggen checkpoint\nsetup {}\nposition {}\nwritten {}\nbytes {}\n",
+            self.setup, position, written, bytes
+        );
+        let temp = format!("{}.tmp", self.path);
+        std::fs::write(&temp, text)
+            .and_then(|()| std::fs::rename(&temp, &self.path))
+            .map_err(|e| Error::Runtime(format!("{}: {}", self.path, e)))?;
+        self.position = Some(position);
+        self.written = written;
+        self.bytes = bytes;
+        Ok(())
+    }
+}
+
+/// What a checkpoint must agree with to be resumed: the pattern and every
+/// option that changes which strings are written, in what order or how.
+fn setup(options: &Options, pattern: &[u8]) -> String {
+    let hex: String = pattern.iter().map(|ch| format!("{:02x}", ch)).collect();
+    format!(
+        "pattern={} max-repeat={} min-length={} max-length={:?} count={:?} shard={:?} \
+         alphabet={:?} encoding={:?}",
+        hex,
+        options.max_repeat,
+        options.min_len,
+        options.max_len,
+        options.count,
+        options.shard,
+        options.alphabet,
+        options.encoding
+    )
+}
+
+/// A number written in decimal.
+fn decimal(text: &str) -> Option<BigUint> {
+    if text.is_empty() {
+        return None;
+    }
+    text.bytes().try_fold(BigUint::zero(), |n, ch| {
+        let digit = BigUint::from(ch.checked_sub(b'0').filter(|&d| d < 10)? as u64);
+        Some(&(&n * &BigUint::from(10)) + &digit)
+    })
+}
+
+/// Refuse to enumerate `expr` if what `options` let through would come to
+/// more than the threshold, counting a separator after each string.
+fn preflight(options: &Options, expr: &Expr) -> Result<(), Error> {
+    let size = estimate(expr)
+        .ok_or_else(|| Error::Runtime("the pattern has no end of strings".to_string()))?;
+    let mut bytes = &size.bytes + &size.strings;
+    if let Some((_, shards)) = options.shard {
+        bytes = bytes.div_rem(&BigUint::from(shards)).0;
+    }
+    let longest = BigUint::from(size.longest as u64 + 1);
+    // Each string written is at most the longest and a separator
+    for cap in [options.count, options.max_lines].iter().flatten() {
+        bytes = bytes.min(&BigUint::from(*cap) * &longest);
+    }
+    if let Some(cap) = options.max_bytes {
+        bytes = bytes.min(BigUint::from(cap));
+    }
+    match bytes > BigUint::from(options.threshold) {
+        true => Err(Error::Runtime(format!(
+            "enumerating would write {}{} bytes, more than the threshold of {}; \
+             pass --force to go ahead",
+            if size.exact { "" } else { "about " },
+            bytes,
+            options.threshold
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Generate what `options` ask for from `expr`, writing each string, or
+/// the count, to `out` tagged with `line`. Stops early once `out` is full.
+/// When enumerating with a checkpoint, starts where it says and keeps it
+/// up to date.
+fn generate(
+    options: &Options,
+    expr: &Expr,
+    out: &mut dyn RecordWriter,
+    line: Option<usize>,
+    mut checkpoint: Option<&mut Checkpoint>,
+) -> Result<(), Error> {
+    let max_len = options.max_len.unwrap_or(usize::MAX);
+    let fits = |s: &[u8]| s.len() >= options.min_len && s.len() <= max_len;
+    let mut seen = HashSet::new();
+    // True the first time a string is seen, or always without `--unique`
+    let mut fresh = |s: &[u8]| !options.unique || seen.insert(s.to_vec());
+    match options.mode {
+        Mode::Enumerate => {
+            if !options.force {
+                preflight(options, expr)?;
+            }
+            let limit = options.count.unwrap_or(u64::MAX);
+            let (mut start, end) = match options.shard {
+                // `args` has checked that the shard is one of those there
+                // are, so only an endless pattern has none
+                Some((k, n)) => {
+                    let range = shard(expr, k, n).ok_or_else(|| {
+                        Error::Runtime("the pattern has no end of strings".to_string())
+                    })?;
+                    (range.start, Some(range.end))
+                }
+                None => (BigUint::zero(), None),
+            };
+            let mut written = 0;
+            if let Some(Checkpoint {
+                position: Some(position),
+                written: before,
+                ..
+            }) = checkpoint.as_deref()
+            {
+                start = position.clone();
+                written = *before;
+            }
+            let mut serial = match &end {
+                Some(end) => Enumerator::between(expr, &start, end),
+                None => Enumerator::starting_at(expr, &start),
+            };
+            let mut parallel = (options.threads > 1).then(|| {
+                let strings = Parallel::new(expr)
+                    .threads(options.threads)
+                    .ordered(options.ordered);
+                match end.clone() {
+                    Some(end) => strings.between(start.clone(), end),
+                    None => strings.starting_at(start.clone()),
+                }
+            });
+            let mut next = |s: &mut Vec<u8>| match &mut parallel {
+                Some(strings) => strings.next_into(s),
+                None => serial.next_into(s),
+            };
+            // Strings taken from the enumeration and dealt with
+            let (mut s, mut taken) = (Vec::new(), 0);
+            while written < limit && !out.full() && next(&mut s) {
+                if fits(&s) && fresh(&s) {
+                    if !out.write(&s, line)? {
+                        break;
+                    }
+                    written += 1;
+                }
+                taken += 1;
+                if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                    if taken % CHECKPOINT_EVERY == 0 {
+                        out.flush()?;
+                        checkpoint.save(&start + &BigUint::from(taken), written)?;
+                    }
+                }
+            }
+            if let Some(checkpoint) = checkpoint {
+                out.flush()?;
+                checkpoint.save(&start + &BigUint::from(taken), written)?;
+            }
+        }
+        Mode::Random => {
+            let seed = options.seed.unwrap_or_else(|| {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH);
+                now.map_or(0, |d| d.as_nanos() as u64)
+            });
+            let mut sampler = Sampler::new(expr, seed);
+            let mut rejects = 0;
+            let mut written = 0;
+            while written < options.count.unwrap_or(10) && !out.full() {
+                let s = sampler
+                    .sample()
+                    .ok_or_else(|| Error::Runtime("no string matches the pattern".to_string()))?;
+                if fits(&s) && fresh(&s) {
+                    out.write(&s, line)?;
+                    written += 1;
+                    rejects = 0;
+                } else if rejects == MAX_REJECTS {
+                    return Err(Error::Runtime(format!(
+                        "gave up after {} strings in a row were too short, too long or repeated",
+                        MAX_REJECTS
+                    )));
+                } else {
+                    rejects += 1;
+                }
+            }
+        }
+        Mode::Count => {
+            let longest = match options.max_len {
+                Some(max) => max,
+                None => expr.max_len().ok_or_else(|| {
+                    Error::Runtime("the pattern has no end of strings".to_string())
+                })?,
+            };
+            let total = count(expr, options.min_len..=longest);
+            out.write_count(&total.strings, total.exact, line)?;
+        }
+        Mode::Estimate => {
+            let size = estimate(expr)
+                .ok_or_else(|| Error::Runtime("the pattern has no end of strings".to_string()))?;
+            let report = format!(
+                "{} strings, {}{} bytes, longest {}",
+                size.strings,
+                if size.exact { "" } else { "about " },
+                size.bytes,
+                size.longest
+            );
+            out.write(report.as_bytes(), line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compiles `node` with its repeats bounded by `--max-repeat`, or by the
+/// length when counting up to `--max-length`, as no string that short
+/// needs more iterations.
+fn compile(options: &Options, node: &ast::Node) -> Expr {
+    let extra = match (options.mode, options.max_len) {
+        (Mode::Count, Some(max)) => max,
+        _ => options.max_repeat,
+    };
+    compile_with(node, &options.alphabet).bounded(extra)
+}
+
+fn run(
+    options: &Options,
+    pattern: &[u8],
+    out: &mut dyn RecordWriter,
+    checkpoint: Option<&mut Checkpoint>,
+) -> Result<(), Error> {
+    let node = reggen::parse(pattern).map_err(Error::Parse)?;
+    generate(options, &compile(options, &node), out, None, checkpoint)?;
+    out.finish()?;
     Ok(())
 }
+
+/// Like `run` for every pattern of a pattern file, reporting the ones that
+/// fail to `errors` and carrying on with the rest.
+fn run_batch(
+    options: &Options,
+    text: &[u8],
+    out: &mut dyn RecordWriter,
+    errors: &mut dyn Write,
+) -> Result<(), Error> {
+    let (mut failed, mut unparsed) = (0, false);
+    for entry in patterns(text) {
+        if out.full() {
+            break;
+        }
+        let result = entry.parse().map_err(Error::Parse).and_then(|node| {
+            generate(
+                options,
+                &compile(options, &node),
+                out,
+                Some(entry.line),
+                None,
+            )
+        });
+        match result {
+            Ok(()) => (),
+            Err(Error::Io(e)) => return Err(Error::Io(e)),
+            Err(e) => {
+                writeln!(errors, "line {}: {}", entry.line, e)?;
+                failed += 1;
+                unparsed |= matches!(e, Error::Parse(_));
+            }
+        }
+    }
+    out.finish()?;
+    match failed {
+        0 => Ok(()),
+        _ => Err(Error::Batch(failed, unparsed)),
+    }
+}
+
+/// Where records go: the output file if given, added to when `append` is
+/// set, or else standard output, within the budget `options` set.
+fn create(options: &Options, append: bool) -> Result<Box<dyn RecordWriter>, Error> {
+    fn encoder<W: Write + 'static>(options: &Options, out: W) -> Box<dyn RecordWriter> {
+        let encoder = Encoder::new(out, options.encoding)
+            .max_records(options.max_lines.unwrap_or(u64::MAX))
+            .max_bytes(options.max_bytes.unwrap_or(u64::MAX));
+        Box::new(encoder)
+    }
+    Ok(match &options.output {
+        Some(path) => {
+            let file = File::options()
+                .write(true)
+                .create(true)
+                .append(append)
+                .truncate(!append)
+                .open(path)
+                .map_err(|e| Error::Runtime(format!("{}: {}", path, e)))?;
+            encoder(options, BufWriter::new(file))
+        }
+        None => encoder(
+            options,
+            BufWriter::with_capacity(OUT_BUFFER, io::stdout().lock()),
+        ),
+    })
+}
+
+/// Generate from one pattern into the output `options` name, resuming
+/// from the checkpoint if there is one.
+fn single(options: &Options, pattern: &[u8]) -> Result<(), Error> {
+    let mut checkpoint = match &options.checkpoint {
+        Some(path) => Some(Checkpoint::open(
+            path,
+            setup(options, pattern),
+            options.output.as_deref(),
+        )?),
+        None => None,
+    };
+    let resuming = checkpoint.as_ref().is_some_and(|c| c.position.is_some());
+    if let Some(checkpoint) = &checkpoint {
+        checkpoint.rewind()?;
+    }
+    let mut out = create(options, resuming)?;
+    run(options, pattern, &mut *out, checkpoint.as_mut())
+}
+
+fn main() {
+    let result = parse_args(std::env::args().skip(1)).and_then(|options| {
+        if options.help {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        if let Some(path) = &options.file {
+            let text =
+                std::fs::read(path).map_err(|e| Error::Runtime(format!("{}: {}", path, e)))?;
+            let errors = &mut io::stderr();
+            let mut out = create(&options, false)?;
+            return run_batch(&options, &text, &mut *out, errors);
+        }
+        let pattern = match &options.pattern {
+            Some(pattern) => pattern.clone(),
+            None => {
+                let mut pattern = Vec::new();
+                io::stdin().read_to_end(&mut pattern)?;
+                // A trailing line break ends the line rather than the pattern
+                if pattern.last() == Some(&b'\n') {
+                    pattern.pop();
+                    if pattern.last() == Some(&b'\r') {
+                        pattern.pop();
+                    }
+                }
+                pattern
+            }
+        };
+        single(&options, &pattern)
+    });
+    match result {
+        // Whatever reads the output has all it wants
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => (),
+        Err(e) => {
+            eprintln!("reggen: {}", e);
+            std::process::exit(e.code());
+        }
+        Ok(()) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Options, Error> {
+        parse_args(line.split_whitespace().map(String::from))
+    }
+
+    fn output(line: &str) -> Result<String, Error> {
+        let options = args(line)?;
+        let mut out = Encoder::new(Vec::new(), options.encoding)
+            .max_records(options.max_lines.unwrap_or(u64::MAX))
+            .max_bytes(options.max_bytes.unwrap_or(u64::MAX));
+        let pattern = options.pattern.as_ref().unwrap();
+        let mut checkpoint = match &options.checkpoint {
+            Some(path) => Some(Checkpoint::open(path, setup(&options, pattern), None)?),
+            None => None,
+        };
+        run(&options, pattern, &mut out, checkpoint.as_mut())?;
+        Ok(String::from_utf8(out.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn args_a() {
+        let options = args("-m random -n 5 --seed=7 -r 2 --max-length 4 -u a+").unwrap();
+        assert_eq!(options.mode, Mode::Random);
+        assert_eq!((options.count, options.seed), (Some(5), Some(7)));
+        assert_eq!((options.max_repeat, options.max_len), (2, Some(4)));
+        assert!(options.unique);
+        assert_eq!(options.pattern, Some(b"a+".to_vec()));
+        assert_eq!(args("-a a-c").unwrap().alphabet.class().len(), 3);
+        assert_eq!(args("-- -x").unwrap().pattern, Some(b"-x".to_vec()));
+        for bad in &[
+            "-m fast",
+            "-n",
+            "-n x",
+            "--nope",
+            "a b",
+            "-a ^a",
+            "--min-length 3 --max-length 2",
+            "-j 0",
+            "--shard 0/2",
+            "--shard 3/2",
+            "--shard 1",
+            "-m count --shard 1/2",
+            "-m random -j 2",
+            "-m estimate --ordered",
+            "--threshold 2x",
+            "--threshold 99999999999T",
+            "--checkpoint x -u",
+            "--checkpoint x -j 2",
+            "--checkpoint x -m random",
+            "--checkpoint x -f y",
+        ] {
+            assert_eq!(args(bad).unwrap_err().code(), 2, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn run_a() {
+        assert_eq!(output("[ab]{1,2}").unwrap(), "a\nb\naa\nab\nba\nbb\n");
+        assert_eq!(output("-n 3 --min-length 2 a*").unwrap(), "aa\naaa\naaaa\n");
+        assert_eq!(output("-u (a|a)b").unwrap(), "ab\n");
+        assert_eq!(output("-m count -r 3 [ab]+").unwrap(), "30\n");
+        assert_eq!(output("-m count ([ab])\\1").unwrap(), "2\n");
+        assert_eq!(output("-m count --max-length 20 a+").unwrap(), "20\n");
+        assert_eq!(output("-m count -r 1 --max-length 3 a*b*").unwrap(), "10\n");
+        assert_eq!(
+            output("-m count -e jsonl [ab]").unwrap(),
+            "{\"count\":2,\"exact\":true}\n"
+        );
+        assert_eq!(
+            output("-m count -e json -r 0 ([a-z]{4})\\1").unwrap(),
+            "[\n{\"count\":208827521552,\"exact\":false}\n]\n"
+        );
+        assert_eq!(
+            output("-m count -r 0 ([a-z]{4})\\1").unwrap(),
+            "at most 208827521552\n"
+        );
+        assert_eq!(
+            output("-m count --min-length 2 --max-length 2 [ab]+").unwrap(),
+            "4\n"
+        );
+        let random = output("-m random -n 20 -s 1 --max-length 3 [a-z]{2,6}").unwrap();
+        assert_eq!(random.lines().count(), 20);
+        assert!(random.lines().all(|s| (2..=3).contains(&s.len())));
+        assert_eq!(
+            random,
+            output("-m random -n 20 -s 1 --max-length 3 [a-z]{2,6}").unwrap()
+        );
+        assert_eq!(
+            output("--max-lines 3 [a-z0-9_-]{6,18}")
+                .unwrap()
+                .lines()
+                .count(),
+            3
+        );
+        assert_eq!(
+            output("--max-bytes 10 [ab]{1,2}").unwrap(),
+            "a\nb\naa\nab\n"
+        );
+        assert_eq!(
+            output("-m random --max-lines 4 a").unwrap(),
+            "a\n".repeat(4)
+        );
+        assert_eq!(
+            output("-j 3 --ordered -r 2 [a-c]+x?").unwrap(),
+            output("-r 2 [a-c]+x?").unwrap()
+        );
+        let mut lines: Vec<_> = output("-j 4 -r 3 (a|bc){2,}")
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.sort();
+        let mut serial: Vec<_> = output("-r 3 (a|bc){2,}")
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        serial.sort();
+        assert_eq!(lines, serial);
+        let shards: String = (1..=3)
+            .map(|k| output(&format!("--shard {}/3 -r 2 (a|bc)+\\1?", k)).unwrap())
+            .collect();
+        assert_eq!(shards, output("-r 2 (a|bc)+\\1?").unwrap());
+        assert_eq!(
+            output("--shard 2/2 -j 2 --ordered [0-9]{2}").unwrap(),
+            output("--shard 2/2 [0-9]{2}").unwrap()
+        );
+        assert!(output("--shard 2/2 [0-9]{2}").unwrap().starts_with("50\n"));
+        assert_eq!(
+            output("-m estimate -r 2 [ab]{1,2}x?").unwrap(),
+            "12 strings, 26 bytes, longest 3\n"
+        );
+        assert_eq!(
+            output("-m estimate (a|bcd)-\\1").unwrap(),
+            "2 strings, about 10 bytes, longest 7\n"
+        );
+        let e = output("--threshold 64K [a-z]{4}").unwrap_err();
+        assert_eq!(
+            (e.code(), e.to_string().contains(" 2284880 bytes")),
+            (1, true)
+        );
+        assert_eq!(
+            output("--force --threshold 1K [a-z]{3}").unwrap().len(),
+            17576 * 4
+        );
+        assert_eq!(
+            output("--threshold 1K -n 2 [a-z]{3}").unwrap(),
+            "aaa\naab\n"
+        );
+        assert!(output("--threshold 1K --max-bytes 1024 [a-z]{3}").is_ok());
+        assert!(output("--threshold 1K --shard 7/10 [a-z]{3}").is_err());
+        assert!(output("--threshold 1K --shard 7/100 [a-z]{3}").is_ok());
+        assert_eq!(output("a(").unwrap_err().code(), 3);
+        assert_eq!(output("-m random -u a").unwrap_err().code(), 1);
+    }
+
+    #[test]
+    fn checkpoint_a() {
+        let path = std::env::temp_dir().join(format!("reggen-{}.checkpoint", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let run = |line: &str| output(&format!("--checkpoint {} {}", path, line));
+        let all = output("-r 1 --shard 2/2 [ab]{1,3}x?").unwrap();
+        let mut parts = Vec::new();
+        parts.push(run("-r 1 --shard 2/2 --max-lines 3 [ab]{1,3}x?").unwrap());
+        parts.push(run("-r 1 --shard 2/2 --max-bytes 7 -j 2 --ordered [ab]{1,3}x?").unwrap());
+        assert_eq!(run("-r 2 --shard 2/2 [ab]{1,3}x?").unwrap_err().code(), 1);
+        assert_eq!(run("-r 1 --shard 1/2 [ab]{1,3}x?").unwrap_err().code(), 1);
+        parts.push(run("-r 1 --shard 2/2 [ab]{1,3}x?").unwrap());
+        parts.push(run("-r 1 --shard 2/2 [ab]{1,3}x?").unwrap());
+        assert_eq!(parts[0].lines().count(), 3);
+        assert_eq!(parts[3], "");
+        assert_eq!(parts.concat(), all);
+        let text = std::fs::read_to_string(path).unwrap();
+        assert!(text.contains(&format!("written {}\n", all.lines().count())));
+        assert!(text.ends_with("bytes 0\n"));
+        std::fs::write(path, "junk").unwrap();
+        assert_eq!(run("[ab]").unwrap_err().code(), 1);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            decimal("12345678901234567890").unwrap().to_string(),
+            "12345678901234567890"
+        );
+        assert_eq!((decimal(""), decimal("1x")), (None, None));
+    }
+
+    #[test]
+    fn checkpoint_b() {
+        let temp = |ext: &str| {
+            let path =
+                std::env::temp_dir().join(format!("reggen-b-{}.{}", std::process::id(), ext));
+            path.to_str().unwrap().to_string()
+        };
+        let (path, out) = (temp("checkpoint"), temp("out"));
+        let _ = std::fs::remove_file(&path);
+        let run = |extra: &str| {
+            let line = format!("--checkpoint {} -o {} {}[ab]{{1,3}}", path, out, extra);
+            single(&args(&line).unwrap(), b"[ab]{1,3}")
+        };
+        run("--max-lines 3 ").unwrap();
+        // A run killed after writing past the last update
+        let mut file = File::options().append(true).open(&out).unwrap();
+        file.write_all(b"ba\nbb\n").unwrap();
+        run("").unwrap();
+        let all = output("[ab]{1,3}").unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), all);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn batch_a() {
+        let options = args("-m random -n 2 -s 3 -r 1").unwrap();
+        let text = b"# numbers\n\\d\n\na(\n[xy]z\n";
+        let (mut out, mut errors) = (Encoder::new(Vec::new(), Encoding::Lines), Vec::new());
+        let e = run_batch(&options, text, &mut out, &mut errors).unwrap_err();
+        assert_eq!(
+            (e.to_string(), e.code()),
+            ("1 pattern failed".to_string(), 3)
+        );
+        let out = String::from_utf8(out.into_inner()).unwrap();
+        let lines: Vec<_> = out.lines().map(|l| l.split('\t').next().unwrap()).collect();
+        assert_eq!(lines, ["2", "2", "5", "5"]);
+        assert!(String::from_utf8(errors)
+            .unwrap()
+            .starts_with("line 4: unbalanced"));
+        let options = args("-m count").unwrap();
+        let mut out = Encoder::new(Vec::new(), Encoding::Lines);
+        run_batch(&options, b"[ab]\nx{2}|y", &mut out, &mut Vec::new()).unwrap();
+        assert_eq!(out.into_inner(), b"1\t2\n2\t2\n");
+        let options = args("-m count -e jsonl -r 0").unwrap();
+        let mut out = Encoder::new(Vec::new(), options.encoding);
+        run_batch(&options, b"[ab]\n([a-z]{4})\\1", &mut out, &mut Vec::new()).unwrap();
+        assert_eq!(
+            String::from_utf8(out.into_inner()).unwrap(),
+            "{\"line\":1,\"count\":2,\"exact\":true}\n\
+             {\"line\":2,\"count\":208827521552,\"exact\":false}\n"
+        );
+        let options = args("-e jsonl -n 1").unwrap();
+        let mut out = Encoder::new(Vec::new(), options.encoding);
+        run_batch(&options, b"\n\\n", &mut out, &mut Vec::new()).unwrap();
+        assert_eq!(out.into_inner(), b"{\"line\":2,\"string\":\"\\n\"}\n");
+        let options = args("--max-lines 3").unwrap();
+        let mut out = Encoder::new(Vec::new(), Encoding::Lines).max_records(3);
+        run_batch(&options, b"[ab]\n[cd]\n(", &mut out, &mut Vec::new()).unwrap();
+        assert_eq!(out.into_inner(), b"1\ta\n1\tb\n2\tc\n");
+    }
+}
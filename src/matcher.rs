@@ -0,0 +1,256 @@
+use crate::error::RenderError;
+use crate::expr::{Captures, Expr};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Longest string matched. Each byte matched nests the search one level
+/// deeper, so longer ones could run out of stack.
+pub(crate) const LONGEST: usize = 1 << 11;
+
+/// Steps a search may take before giving up. Backtracking can take
+/// exponential time, as with `(a*)*b` against a run of `a`s.
+pub(crate) const STEPS: usize = 1 << 20;
+
+/// Called with the end offset of each way a part matches, until it
+/// accepts one.
+type Found<'f, 's> = &'f mut dyn FnMut(&mut Matcher<'s>, usize) -> bool;
+
+/// Backtracking matcher over the compiled expression.
+struct Matcher<'s> {
+    s: &'s [u8],
+    fixed: &'s HashMap<usize, Vec<u8>>,
+    groups: Captures,
+    /// Steps left before giving up.
+    steps: usize,
+    /// End offsets of plain parts, by part and start.
+    memo: HashMap<(*const Expr, usize), Rc<Vec<usize>>>,
+}
+
+/// True if `expr` has no groups or backreferences, so that what it
+/// matches doesn't depend on the rest of the pattern.
+pub(crate) fn plain(expr: &Expr) -> bool {
+    expr.groups() == 0 && !expr.has_refs()
+}
+
+/// Offsets `expr` can reach from any of those set in `from`.
+fn reach(expr: &Expr, s: &[u8], from: &[bool]) -> Vec<bool> {
+    let mut to = vec![false; from.len()];
+    match expr {
+        Expr::Empty | Expr::Ref(_) => to.copy_from_slice(from),
+        Expr::Byte(b) => {
+            for (pos, &ch) in s.iter().enumerate().filter(|&(pos, _)| from[pos]) {
+                to[pos + 1] = ch == *b;
+            }
+        }
+        Expr::Class(set) => {
+            for (pos, &ch) in s.iter().enumerate().filter(|&(pos, _)| from[pos]) {
+                to[pos + 1] = set.contains(ch);
+            }
+        }
+        Expr::Seq(exprs) => {
+            to.copy_from_slice(from);
+            for e in exprs {
+                to = reach(e, s, &to);
+            }
+        }
+        Expr::Alt(exprs) => {
+            for e in exprs {
+                let ends = reach(e, s, from);
+                to.iter_mut().zip(ends).for_each(|(t, e)| *t |= e);
+            }
+        }
+        Expr::Repeat(inner, min, max) => {
+            let mut exact = from.to_vec();
+            for _ in 0..*min {
+                exact = reach(inner, s, &exact);
+            }
+            to = exact.clone();
+            // Once an iteration reaches nothing new, no later one can
+            for _ in *min..*max {
+                exact = reach(inner, s, &exact);
+                if exact.iter().zip(&to).all(|(e, t)| !e || *t) {
+                    break;
+                }
+                to.iter_mut().zip(&exact).for_each(|(t, e)| *t |= e);
+            }
+        }
+        Expr::Group(_, inner) => to = reach(inner, s, from),
+    }
+    to
+}
+
+impl<'s> Matcher<'s> {
+    fn walk(&mut self, expr: &Expr, pos: usize, found: Found<'_, 's>) -> bool {
+        if self.steps == 0 {
+            return false;
+        }
+        self.steps -= 1;
+        match expr {
+            Expr::Empty => found(self, pos),
+            Expr::Byte(ch) => self.s.get(pos) == Some(ch) && found(self, pos + 1),
+            Expr::Class(set) => match self.s.get(pos) {
+                Some(&ch) => set.contains(ch) && found(self, pos + 1),
+                None => false,
+            },
+            Expr::Seq(exprs) => {
+                let row = exprs.iter().collect::<Vec<_>>();
+                self.row(&row, pos, found)
+            }
+            Expr::Alt(exprs) => exprs.iter().any(|e| self.walk(e, pos, found)),
+            // Plain repeats are matched by offset sets, without trying each
+            // way of splitting the bytes between iterations
+            Expr::Repeat(..) if plain(expr) => {
+                let ends = self.ends(expr, pos);
+                ends.iter().any(|&end| found(self, end))
+            }
+            Expr::Repeat(inner, min, max) => self.repeat(inner, *min, *max, pos, found),
+            Expr::Group(n, inner) => self.walk(inner, pos, &mut |m, end| {
+                if let Some(value) = m.fixed.get(n) {
+                    if m.s[pos..end] != value[..] {
+                        return false;
+                    }
+                }
+                let before = m.groups.get(*n);
+                m.groups.set(*n, Some(pos..end));
+                if found(m, end) {
+                    return true;
+                }
+                m.groups.set(*n, before);
+                false
+            }),
+            Expr::Ref(n) => {
+                let span = self.groups.get(*n).unwrap_or(0..0);
+                let end = pos + span.len();
+                self.s[pos..].starts_with(&self.s[span]) && found(self, end)
+            }
+        }
+    }
+
+    /// Offsets a plain `expr` can reach from `pos`, shortest first.
+    fn ends(&mut self, expr: &Expr, pos: usize) -> Rc<Vec<usize>> {
+        let key = (expr as *const Expr, pos);
+        if let Some(ends) = self.memo.get(&key) {
+            return ends.clone();
+        }
+        let mut from = vec![false; self.s.len() + 1];
+        from[pos] = true;
+        let ends = reach(expr, self.s, &from);
+        let ends = Rc::new(
+            (pos..ends.len())
+                .filter(|&end| ends[end])
+                .collect::<Vec<_>>(),
+        );
+        self.memo.insert(key, ends.clone());
+        ends
+    }
+
+    /// `inner` at least `min` and at most `max` more times, fewest first.
+    /// Past the minimum only iterations that match something are tried, as
+    /// empty ones can't lead anywhere new.
+    fn repeat(
+        &mut self,
+        inner: &Expr,
+        min: usize,
+        max: usize,
+        pos: usize,
+        found: Found<'_, 's>,
+    ) -> bool {
+        if min == 0 && found(self, pos) {
+            return true;
+        }
+        max > 0
+            && self.walk(inner, pos, &mut |m, end| {
+                (min > 0 || end > pos)
+                    && m.repeat(inner, min.saturating_sub(1), max - 1, end, found)
+            })
+    }
+
+    fn row(&mut self, row: &[&Expr], pos: usize, found: Found<'_, 's>) -> bool {
+        match row.split_first() {
+            None => found(self, pos),
+            Some((first, rest)) => self.walk(first, pos, &mut |m, end| m.row(rest, end, found)),
+        }
+    }
+}
+
+/// Where each group matched, if `expr` can produce exactly `s` with every
+/// group in `fixed` that takes part matching its given value. Strings
+/// longer than [`LONGEST`] and searches longer than [`STEPS`] are given
+/// up on with [`RenderError::TooComplex`].
+pub fn captures(
+    expr: &Expr,
+    s: &[u8],
+    fixed: &HashMap<usize, Vec<u8>>,
+) -> Result<Option<Captures>, RenderError> {
+    if s.len() > LONGEST {
+        return Err(RenderError::TooComplex);
+    }
+    let mut matcher = Matcher {
+        s,
+        fixed,
+        groups: Captures::default(),
+        steps: STEPS,
+        memo: HashMap::new(),
+    };
+    if matcher.walk(expr, 0, &mut |_, end| end == s.len()) {
+        matcher.groups.set(0, Some(0..s.len()));
+        Ok(Some(matcher.groups))
+    } else if matcher.steps == 0 {
+        Err(RenderError::TooComplex)
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::compile;
+    use crate::parse;
+
+    fn captures_re(re: &str, s: &str, fixed: &[(usize, &str)]) -> Option<Captures> {
+        let expr = compile(&parse(re.as_bytes()).unwrap());
+        let fixed = fixed
+            .iter()
+            .map(|(n, v)| (*n, v.as_bytes().to_vec()))
+            .collect();
+        captures(&expr, s.as_bytes(), &fixed).unwrap()
+    }
+
+    #[test]
+    fn matcher_a() {
+        assert!(captures_re("a[b-d]*e", "abcde", &[]).is_some());
+        assert!(captures_re("a[b-d]*e", "abxe", &[]).is_none());
+        assert!(captures_re("(a|ab)(c|bcd)(d*)", "abcd", &[]).is_some());
+        assert!(captures_re(r"(\w+)-\1", "ab-ab", &[]).is_some());
+        assert!(captures_re(r"(\w+)-\1", "ab-ba", &[]).is_none());
+    }
+
+    #[test]
+    fn matcher_b() {
+        let groups = captures_re("(a*)(a*)", "aaa", &[]).unwrap();
+        assert_eq!(groups.get(1), Some(0..0));
+        let groups = captures_re("(a*)(a*)", "aaa", &[(2, "a")]).unwrap();
+        assert_eq!(groups.get(1), Some(0..2));
+        assert_eq!(groups.get(2), Some(2..3));
+        assert!(captures_re("(a*)(a*)", "aaa", &[(2, "b")]).is_none());
+    }
+
+    #[test]
+    fn matcher_c() {
+        let expr = compile(&parse(b"(a*)*b").unwrap());
+        let run = vec![b'a'; 40];
+        assert_eq!(
+            captures(&expr, &run, &HashMap::new()),
+            Err(RenderError::TooComplex)
+        );
+        let expr = compile(&parse(br"(\w+)-\1").unwrap());
+        let long = [vec![b'x'; LONGEST / 2 - 1], vec![b'x'; LONGEST / 2 - 1]].join(&b'-');
+        assert!(captures(&expr, &long, &HashMap::new()).unwrap().is_some());
+        let longer = vec![b'x'; LONGEST + 1];
+        assert_eq!(
+            captures(&expr, &longer, &HashMap::new()),
+            Err(RenderError::TooComplex)
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::ast::{GroupKind, Node};
+
+/// Append `node` to a sequence, merging it into the literal before it when
+/// both are literals.
+fn push(seq: &mut Vec<Node>, node: Node) {
+    match (seq.last_mut(), node) {
+        (Some(Node::Text(last)), Node::Text(bytes)) => last.extend(bytes),
+        (_, node) => seq.push(node),
+    }
+}
+
+/// Rewrite a tree into its simplest equivalent shape, matching the same
+/// strings as before.
+///
+/// Nested sequences are flattened and adjacent literals merged into one
+/// [`Node::Text`], so a sequence never holds fewer than two nodes. A set of
+/// one byte becomes a literal.
+/// Nested alternations are flattened, `(?:..)` groups and `{1}` repeats
+/// are dropped, and whatever only matches the empty string becomes
+/// [`Node::Empty`]. Capturing groups always stay, so group numbers and
+/// backreferences keep their meaning.
+pub fn normalize(node: Node) -> Node {
+    match node {
+        Node::Text(bytes) if bytes.is_empty() => Node::Empty,
+        Node::Charset {
+            class,
+            negated: false,
+        } if class.len() == 1 => Node::Text(class.iter().collect()),
+        Node::Seq(nodes) => {
+            let mut seq = Vec::with_capacity(nodes.len());
+            for node in nodes.into_iter().map(normalize) {
+                match node {
+                    Node::Empty => (),
+                    Node::Seq(nodes) => nodes.into_iter().for_each(|n| push(&mut seq, n)),
+                    node => push(&mut seq, node),
+                }
+            }
+            match seq.len() {
+                0 => Node::Empty,
+                1 => seq.remove(0),
+                _ => Node::Seq(seq),
+            }
+        }
+        Node::Select(nodes) => {
+            let mut select = Vec::with_capacity(nodes.len());
+            for node in nodes.into_iter().map(normalize) {
+                match node {
+                    Node::Select(nodes) => select.extend(nodes),
+                    node => select.push(node),
+                }
+            }
+            match select.len() {
+                1 => select.remove(0),
+                _ => Node::Select(select),
+            }
+        }
+        Node::Repeat { node, min, max } => match normalize(*node) {
+            Node::Empty => Node::Empty,
+            node if min == 1 && max == Some(1) => node,
+            node => Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            },
+        },
+        Node::Group(node, GroupKind::NonCapture) => normalize(*node),
+        Node::Group(node, kind) => Node::Group(Box::new(normalize(*node)), kind),
+        node => node,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, count, parse, print, CharClass};
+
+    fn normalize_re(re: &str) -> Node {
+        normalize(parse(re.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn normalize_a() {
+        let text = |s: &[u8]| Node::Text(s.to_vec());
+        assert_eq!(
+            normalize_re("ab(?:cd)e"),
+            Node::Seq(vec![Node::Start, text(b"abcde"), Node::End])
+        );
+        assert_eq!(
+            normalize_re("[a-z|0-9c]x[q]"),
+            Node::Seq(vec![
+                Node::Start,
+                Node::Charset {
+                    class: CharClass::range(b'0', b'9').union(CharClass::range(b'a', b'z')),
+                    negated: false
+                },
+                text(b"xq"),
+                Node::End
+            ])
+        );
+        assert_eq!(
+            normalize_re("(?:a|(?:b|c))d{1}()"),
+            Node::Seq(vec![
+                Node::Start,
+                Node::Select(vec![text(b"a"), text(b"b"), text(b"c")]),
+                text(b"d"),
+                Node::Group(Box::new(Node::Empty), GroupKind::Capture(1)),
+                Node::End
+            ])
+        );
+        assert_eq!(
+            normalize_re("(?:ab)*(?:)+"),
+            Node::Seq(vec![
+                Node::Start,
+                Node::Repeat {
+                    node: Box::new(text(b"ab")),
+                    min: 0,
+                    max: None
+                },
+                Node::End
+            ])
+        );
+    }
+
+    #[test]
+    fn normalize_b() {
+        for re in crate::CORPUS {
+            let ast = parse(re.as_bytes()).unwrap();
+            let norm = normalize(ast.clone());
+            assert_eq!(normalize(norm.clone()), norm, "{}", re);
+            let again = normalize(parse(&print(&norm)).unwrap());
+            assert_eq!(again, norm, "{}", re);
+            assert_eq!(
+                count(&compile(&ast), 0..=6),
+                count(&compile(&norm), 0..=6),
+                "{}",
+                re
+            );
+        }
+    }
+}
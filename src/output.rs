@@ -0,0 +1,387 @@
+use crate::bigint::BigUint;
+use std::io::{self, Write};
+
+/// Somewhere generated strings go, one record at a time.
+pub trait RecordWriter {
+    /// Write one string, tagged with the line of the pattern file it was
+    /// generated from, if there is one. False if the output is full and
+    /// the string was left out.
+    fn write(&mut self, record: &[u8], line: Option<usize>) -> io::Result<bool>;
+
+    /// Write a count of strings in place of a string, `exact` or else an
+    /// upper bound. By default it is written as its decimal digits, after
+    /// `at most` when it is a bound.
+    fn write_count(
+        &mut self,
+        count: &BigUint,
+        exact: bool,
+        line: Option<usize>,
+    ) -> io::Result<bool> {
+        self.write(count_text(count, exact).as_bytes(), line)
+    }
+
+    /// Pass on everything written so far.
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Close off the output after the last record, and flush it.
+    fn finish(&mut self) -> io::Result<()>;
+
+    /// True once the output wants no more records, so that generating
+    /// them can stop.
+    fn full(&self) -> bool {
+        false
+    }
+}
+
+/// How records are written out. Generated strings can hold any byte, line
+/// breaks included, so only some encodings keep them apart for certain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    /// Each string followed by `\n`. Ambiguous when strings hold `\n`.
+    Lines,
+    /// Each string followed by a NUL byte.
+    Nul,
+    /// A JSON array with one value per line.
+    Json,
+    /// One JSON value per line.
+    JsonLines,
+    /// RFC 4180 CSV, quoting fields that need it.
+    Csv,
+    /// One line per string, with `\` and every byte outside printable
+    /// ASCII written as an escape such as `\n` or `\xff`.
+    Hex,
+}
+
+/// Writes records in one of the [`Encoding`]s.
+///
+/// A record tagged with a line starts with the line number and a tab, or
+/// is a `line,string` row in CSV. In JSON a string is a JSON string when
+/// it is valid UTF-8 and otherwise an array of its bytes, and a tagged
+/// record is an object holding `line` and either `string` or `bytes`. A
+/// count is an object holding `count` as a JSON number and `exact`, and
+/// `line` when tagged.
+pub struct Encoder<W: Write> {
+    out: W,
+    encoding: Encoding,
+    records: u64,
+    bytes: u64,
+    max_records: u64,
+    max_bytes: u64,
+    full: bool,
+    /// Each record is encoded here before it is written, reusing the room.
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(out: W, encoding: Encoding) -> Self {
+        Self {
+            out,
+            encoding,
+            records: 0,
+            bytes: 0,
+            max_records: u64::MAX,
+            max_bytes: u64::MAX,
+            full: false,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Stop after `records` records.
+    pub fn max_records(mut self, records: u64) -> Self {
+        self.max_records = records;
+        self
+    }
+
+    /// Stop before the record that would take the output past `bytes`
+    /// bytes, not counting the `]` closing a JSON array. Records are never
+    /// cut short.
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = bytes;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+fn json_string(out: &mut Vec<u8>, s: &str) {
+    out.push(b'"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.extend_from_slice(br#"\""#),
+            '\\' => out.extend_from_slice(br"\\"),
+            '\n' => out.extend_from_slice(br"\n"),
+            '\r' => out.extend_from_slice(br"\r"),
+            '\t' => out.extend_from_slice(br"\t"),
+            '\0'..='\x1f' => out.extend_from_slice(format!("\\u{:04x}", ch as u32).as_bytes()),
+            _ => out.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    out.push(b'"');
+}
+
+fn count_text(count: &BigUint, exact: bool) -> String {
+    match exact {
+        true => count.to_string(),
+        false => format!("at most {}", count),
+    }
+}
+
+/// A record as JSON, or a count object around a count's digits when
+/// `exact` is given.
+fn json(out: &mut Vec<u8>, record: &[u8], line: Option<usize>, exact: Option<bool>) {
+    if let Some(exact) = exact {
+        out.push(b'{');
+        if let Some(line) = line {
+            out.extend_from_slice(format!(r#""line":{},"#, line).as_bytes());
+        }
+        out.extend_from_slice(br#""count":"#);
+        out.extend_from_slice(record);
+        out.extend_from_slice(format!(r#","exact":{}}}"#, exact).as_bytes());
+        return;
+    }
+    let text = std::str::from_utf8(record);
+    if let Some(line) = line {
+        let key = if text.is_ok() { "string" } else { "bytes" };
+        out.extend_from_slice(format!(r#"{{"line":{},"{}":"#, line, key).as_bytes());
+    }
+    match text {
+        Ok(s) => json_string(out, s),
+        Err(_) => {
+            out.push(b'[');
+            for (i, ch) in record.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                out.extend_from_slice(ch.to_string().as_bytes());
+            }
+            out.push(b']');
+        }
+    }
+    if line.is_some() {
+        out.push(b'}');
+    }
+}
+
+fn csv(out: &mut Vec<u8>, record: &[u8]) {
+    if record.iter().any(|ch| b",\"\r\n".contains(ch)) {
+        out.push(b'"');
+        for &ch in record {
+            if ch == b'"' {
+                out.push(b'"');
+            }
+            out.push(ch);
+        }
+        out.push(b'"');
+    } else {
+        out.extend_from_slice(record);
+    }
+}
+
+fn hex(out: &mut Vec<u8>, record: &[u8]) {
+    for &ch in record {
+        match ch {
+            b'\\' => out.extend_from_slice(br"\\"),
+            b'\n' => out.extend_from_slice(br"\n"),
+            b'\r' => out.extend_from_slice(br"\r"),
+            b'\t' => out.extend_from_slice(br"\t"),
+            b' '..=b'~' => out.push(ch),
+            _ => out.extend_from_slice(format!("\\x{:02x}", ch).as_bytes()),
+        }
+    }
+}
+
+impl<W: Write> Encoder<W> {
+    fn record(
+        &mut self,
+        record: &[u8],
+        line: Option<usize>,
+        exact: Option<bool>,
+    ) -> io::Result<bool> {
+        if self.full() {
+            return Ok(false);
+        }
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        let tag = |buf: &mut Vec<u8>| {
+            if let Some(line) = line {
+                buf.extend_from_slice(format!("{}\t", line).as_bytes());
+            }
+        };
+        match self.encoding {
+            Encoding::Lines => {
+                tag(&mut buf);
+                buf.extend_from_slice(record);
+                buf.push(b'\n');
+            }
+            Encoding::Nul => {
+                tag(&mut buf);
+                buf.extend_from_slice(record);
+                buf.push(0);
+            }
+            Encoding::Json => {
+                buf.extend_from_slice(if self.records == 0 { b"[\n" } else { b",\n" });
+                json(&mut buf, record, line, exact);
+            }
+            Encoding::JsonLines => {
+                json(&mut buf, record, line, exact);
+                buf.push(b'\n');
+            }
+            Encoding::Csv => {
+                if let Some(line) = line {
+                    buf.extend_from_slice(format!("{},", line).as_bytes());
+                }
+                csv(&mut buf, record);
+                buf.extend_from_slice(b"\r\n");
+            }
+            Encoding::Hex => {
+                tag(&mut buf);
+                hex(&mut buf, record);
+                buf.push(b'\n');
+            }
+        }
+        let result = match self.bytes.saturating_add(buf.len() as u64) {
+            bytes if bytes > self.max_bytes => {
+                self.full = true;
+                Ok(false)
+            }
+            bytes => {
+                self.records += 1;
+                self.bytes = bytes;
+                self.out.write_all(&buf).map(|()| true)
+            }
+        };
+        self.buf = buf;
+        result
+    }
+}
+
+impl<W: Write> RecordWriter for Encoder<W> {
+    fn write(&mut self, record: &[u8], line: Option<usize>) -> io::Result<bool> {
+        self.record(record, line, None)
+    }
+
+    fn write_count(
+        &mut self,
+        count: &BigUint,
+        exact: bool,
+        line: Option<usize>,
+    ) -> io::Result<bool> {
+        match self.encoding {
+            Encoding::Json | Encoding::JsonLines => {
+                self.record(count.to_string().as_bytes(), line, Some(exact))
+            }
+            _ => self.record(count_text(count, exact).as_bytes(), line, None),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.encoding == Encoding::Json {
+            self.out
+                .write_all(if self.records == 0 { b"[]\n" } else { b"\n]\n" })?;
+        }
+        self.out.flush()
+    }
+
+    fn full(&self) -> bool {
+        self.full || self.records >= self.max_records || self.bytes >= self.max_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(encoding: Encoding, records: &[(&[u8], Option<usize>)]) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new(), encoding);
+        for (record, line) in records {
+            encoder.write(record, *line).unwrap();
+        }
+        encoder.finish().unwrap();
+        encoder.into_inner()
+    }
+
+    #[test]
+    fn encode_a() {
+        let records: &[(&[u8], Option<usize>)] = &[(b"a\nb", None), (b"\"\\\t\x01\xc3\xa9", None)];
+        assert_eq!(
+            encode(Encoding::Lines, records),
+            b"a\nb\n\"\\\t\x01\xc3\xa9\n"
+        );
+        assert_eq!(
+            encode(Encoding::Nul, records),
+            b"a\nb\0\"\\\t\x01\xc3\xa9\0"
+        );
+        assert_eq!(
+            String::from_utf8(encode(Encoding::Json, records)).unwrap(),
+            "[\n\"a\\nb\",\n\"\\\"\\\\\\t\\u0001\u{e9}\"\n]\n"
+        );
+        assert_eq!(encode(Encoding::Json, &[]), b"[]\n");
+        assert_eq!(
+            encode(Encoding::Csv, records),
+            b"\"a\nb\"\r\n\"\"\"\\\t\x01\xc3\xa9\"\r\n"
+        );
+        assert_eq!(
+            encode(Encoding::Hex, records),
+            b"a\\nb\n\"\\\\\\t\\x01\\xc3\\xa9\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn encode_b() {
+        let records: &[(&[u8], Option<usize>)] = &[(b"x,y", Some(3)), (b"\xff\x00", Some(12))];
+        assert_eq!(
+            String::from_utf8(encode(Encoding::JsonLines, records)).unwrap(),
+            "{\"line\":3,\"string\":\"x,y\"}\n{\"line\":12,\"bytes\":[255,0]}\n"
+        );
+        assert_eq!(encode(Encoding::JsonLines, &[(b"\xff", None)]), b"[255]\n");
+        assert_eq!(
+            encode(Encoding::Csv, records),
+            b"3,\"x,y\"\r\n12,\xff\x00\r\n"
+        );
+        assert_eq!(encode(Encoding::Lines, records), b"3\tx,y\n12\t\xff\x00\n");
+        let count = |encoding, exact, line| {
+            let mut encoder = Encoder::new(Vec::new(), encoding);
+            encoder
+                .write_count(&BigUint::from(42), exact, line)
+                .unwrap();
+            encoder.finish().unwrap();
+            String::from_utf8(encoder.into_inner()).unwrap()
+        };
+        assert_eq!(
+            count(Encoding::Json, true, None),
+            "[\n{\"count\":42,\"exact\":true}\n]\n"
+        );
+        assert_eq!(
+            count(Encoding::JsonLines, false, Some(2)),
+            "{\"line\":2,\"count\":42,\"exact\":false}\n"
+        );
+        assert_eq!(count(Encoding::Csv, true, Some(2)), "2,42\r\n");
+        assert_eq!(count(Encoding::Lines, false, None), "at most 42\n");
+    }
+
+    #[test]
+    fn encode_c() {
+        let mut encoder = Encoder::new(Vec::new(), Encoding::Json).max_bytes(16);
+        let written: Vec<_> = [&b"ab"[..], b"cd", b"efgh", b"i"]
+            .iter()
+            .map(|record| encoder.write(record, None).unwrap())
+            .collect();
+        assert_eq!(written, [true, true, false, false]);
+        assert!(encoder.full());
+        encoder.finish().unwrap();
+        assert_eq!(encoder.into_inner(), b"[\n\"ab\",\n\"cd\"\n]\n");
+        let mut encoder = Encoder::new(Vec::new(), Encoding::Lines).max_records(2);
+        assert!(!encoder.full());
+        for record in &[&b"a"[..], b"b", b"c"] {
+            encoder.write(record, None).unwrap();
+        }
+        assert!(encoder.full());
+        assert_eq!(encoder.into_inner(), b"a\nb\n");
+    }
+}
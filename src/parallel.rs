@@ -0,0 +1,230 @@
+use crate::bigint::BigUint;
+use crate::enumerate::Enumerator;
+use crate::expr::Expr;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+/// Blocks a thread may have waiting to be read before it stops to wait.
+const QUEUE: usize = 4;
+
+/// Enumerates on several threads at once.
+///
+/// Enumeration order is cut into blocks of consecutive positions, and
+/// thread `t` of `n` generates blocks `t`, `t + n`, `t + 2n` and so on,
+/// starting each one with [`Enumerator::starting_at`]. The blocks are
+/// disjoint and cover every position, so together the threads produce
+/// exactly what [`Enumerator`] does. When ordered they come back in the
+/// same order; otherwise each block comes back whole as soon as it is
+/// done. Dropping the iterator stops the threads.
+pub struct Parallel {
+    expr: Arc<Expr>,
+    threads: usize,
+    block: u64,
+    ordered: bool,
+    start: BigUint,
+    end: Option<BigUint>,
+    started: Option<Blocks>,
+    current: Block,
+    /// Strings of `current` already produced.
+    taken: usize,
+}
+
+/// Consecutive strings, stored end to end.
+#[derive(Default)]
+struct Block {
+    bytes: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+enum Blocks {
+    /// One queue per thread, read in turn.
+    Ordered(Vec<Receiver<Block>>, usize),
+    /// One queue shared by every thread.
+    Unordered(Receiver<Block>),
+}
+
+impl Parallel {
+    /// One thread for each processor, unordered.
+    pub fn new(expr: &Expr) -> Self {
+        Self {
+            expr: Arc::new(expr.clone()),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            block: 1024,
+            ordered: false,
+            start: BigUint::zero(),
+            end: None,
+            started: None,
+            current: Block::default(),
+            taken: 0,
+        }
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Strings each thread generates at a time.
+    pub fn block(mut self, block: u64) -> Self {
+        self.block = block.max(1);
+        self
+    }
+
+    /// Produce strings in enumeration order.
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Start at position `start` of the order, as with
+    /// [`Enumerator::starting_at`].
+    pub fn starting_at(mut self, start: BigUint) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Only the positions from `start` up to but not including `end`, as
+    /// with [`Enumerator::between`].
+    pub fn between(mut self, start: BigUint, end: BigUint) -> Self {
+        self.start = start;
+        self.end = Some(end);
+        self
+    }
+
+    fn start(&self) -> Blocks {
+        let (shared, unordered) = mpsc::sync_channel(QUEUE * self.threads);
+        let mut queues = Vec::new();
+        for t in 0..self.threads {
+            let send = match self.ordered {
+                true => {
+                    let (send, queue) = mpsc::sync_channel(QUEUE);
+                    queues.push(queue);
+                    send
+                }
+                false => shared.clone(),
+            };
+            let (expr, threads, block) = (self.expr.clone(), self.threads as u64, self.block);
+            let (start, end) = (self.start.clone(), self.end.clone());
+            thread::spawn(move || {
+                for b in (t as u64..).step_by(threads as usize) {
+                    let from = &start + &(&BigUint::from(b) * &BigUint::from(block));
+                    let to = &from + &BigUint::from(block);
+                    let to = match end {
+                        Some(ref end) if *end < to => end.clone(),
+                        _ => to,
+                    };
+                    let mut strings = Enumerator::between(&expr, &from, &to);
+                    let (mut next, mut s) = (Block::default(), Vec::new());
+                    while (next.ends.len() as u64) < block && strings.next_into(&mut s) {
+                        next.bytes.extend_from_slice(&s);
+                        next.ends.push(next.bytes.len());
+                    }
+                    let (empty, last) = (next.ends.is_empty(), (next.ends.len() as u64) < block);
+                    // Nobody is reading once the iterator is dropped
+                    if empty || send.send(next).is_err() || last {
+                        break;
+                    }
+                }
+            });
+        }
+        match self.ordered {
+            true => Blocks::Ordered(queues, 0),
+            false => Blocks::Unordered(unordered),
+        }
+    }
+
+    /// Write the next string into `out` in place of what it held, like
+    /// [`Enumerator::next_into`]. False once there are no more.
+    pub fn next_into(&mut self, out: &mut Vec<u8>) -> bool {
+        while self.taken == self.current.ends.len() {
+            match self.next_block() {
+                Some(block) => self.current = block,
+                None => return false,
+            }
+            self.taken = 0;
+        }
+        let ends = &self.current.ends;
+        let from = if self.taken == 0 {
+            0
+        } else {
+            ends[self.taken - 1]
+        };
+        out.clear();
+        out.extend_from_slice(&self.current.bytes[from..ends[self.taken]]);
+        self.taken += 1;
+        true
+    }
+
+    fn next_block(&mut self) -> Option<Block> {
+        if self.started.is_none() {
+            self.started = Some(self.start());
+        }
+        match self.started.as_mut()? {
+            // A thread only finishes at the end of the order, so the
+            // first finished queue met in turn means there is no more
+            Blocks::Ordered(queues, turn) => {
+                let block = queues[*turn].recv().ok();
+                *turn = (*turn + 1) % queues.len();
+                block
+            }
+            Blocks::Unordered(queue) => queue.recv().ok(),
+        }
+    }
+}
+
+impl Iterator for Parallel {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let mut s = Vec::new();
+        match self.next_into(&mut s) {
+            true => Some(s),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::compile;
+    use crate::parse;
+
+    #[test]
+    fn parallel_a() {
+        for re in &["[a-c]{1,4}", "(x|yz)\\1{0,3}[01]", "", "a|(b|c(d|e)){1,3}"] {
+            let expr = compile(&parse(re.as_bytes()).unwrap());
+            let serial: Vec<_> = Enumerator::new(&expr).collect();
+            for &(threads, block) in &[(1, 1), (3, 2), (4, 7), (2, 1000)] {
+                let strings = Parallel::new(&expr).threads(threads).block(block);
+                let ordered: Vec<_> = strings.ordered(true).collect();
+                assert_eq!(ordered, serial, "{} {} {}", re, threads, block);
+                let mut unordered: Vec<_> =
+                    Parallel::new(&expr).threads(threads).block(block).collect();
+                let mut sorted = serial.clone();
+                unordered.sort();
+                sorted.sort();
+                assert_eq!(unordered, sorted, "{} {} {}", re, threads, block);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_b() {
+        let expr = compile(&parse(b"[a-z]+").unwrap());
+        let strings = Parallel::new(&expr).threads(4).block(10).ordered(true);
+        let first: Vec<_> = strings.take(100).collect();
+        assert_eq!(first, Enumerator::new(&expr).take(100).collect::<Vec<_>>());
+        let strings = Parallel::new(&expr).threads(3).block(4).ordered(true);
+        let middle: Vec<_> = strings
+            .between(BigUint::from(20), BigUint::from(50))
+            .collect();
+        assert_eq!(middle, first[20..50]);
+        let strings = Parallel::new(&expr).threads(2).block(3).ordered(true);
+        let rest: Vec<_> = strings.starting_at(BigUint::from(90)).take(10).collect();
+        assert_eq!(rest, first[90..]);
+        let void = compile(&parse(b"a[^\\s\\S]").unwrap());
+        assert_eq!(Parallel::new(&void).threads(3).next(), None);
+    }
+}
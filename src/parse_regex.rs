@@ -1,15 +1,17 @@
+use crate::class::{CharClass, DIGIT};
 use nom::branch::alt;
 use nom::bytes::complete::{is_a, tag, take};
 use nom::character::complete::{alpha1, digit1};
-use nom::combinator::{map, opt};
+use nom::combinator::{map, map_opt, opt};
 use nom::multi::{many1, separated_list};
 use nom::sequence::{delimited, pair, preceded, terminated, tuple};
-use nom::{error::ErrorKind, IResult};
+use nom::IResult;
 use num_traits::{cast, Num};
-use std::str::from_utf8_unchecked;
+
+use crate::error::{ErrorKind, ParseError};
 
 #[derive(Debug, PartialEq)]
-struct Repeat<T: Num> {
+pub struct Repeat<T: Num> {
     min: T,
     max: T,
 }
@@ -24,9 +26,9 @@ where
 }
 
 #[derive(Debug, PartialEq)]
-enum Node {
-    ExRange(Vec<u8>),
-    Range(Vec<u8>),
+pub enum Node {
+    ExRange(CharClass),
+    Range(CharClass),
     Alternation(Vec<Node>, Option<Repeat<u16>>),
     Group(Vec<Node>, Option<Repeat<u16>>),
     Text(Vec<u8>),
@@ -38,15 +40,22 @@ fn text(input: &[u8]) -> IResult<&[u8], Node> {
     map(is_a(ALPHANUM), |s: &[u8]| Node::Text(s.to_vec()))(input)
 }
 
+/// `\w` here leaves out `_`.
+const ALNUM: CharClass = CharClass::range(b'A', b'Z')
+    .union(CharClass::range(b'a', b'z'))
+    .union(DIGIT);
+/// `\s`
+const SPACE: CharClass = CharClass::from_bytes(b"\t\r\n ");
+
 fn range(input: &[u8]) -> IResult<&[u8], Node> {
     alt((
         map(
             preceded(tag("^"), tuple((take(1usize), tag("-"), take(1usize)))),
-            |(s, _, e): (&[u8], &[u8], &[u8])| Node::ExRange((s[0]..=e[0]).collect::<Vec<_>>()),
+            |(s, _, e): (&[u8], &[u8], &[u8])| Node::ExRange(CharClass::range(s[0], e[0])),
         ),
         map(
             tuple((take(1usize), tag("-"), take(1usize))),
-            |(s, _, e): (&[u8], &[u8], &[u8])| Node::Range((s[0]..=e[0]).collect::<Vec<_>>()),
+            |(s, _, e): (&[u8], &[u8], &[u8])| Node::Range(CharClass::range(s[0], e[0])),
         ),
         backslash,
         text,
@@ -58,36 +67,28 @@ where
     T: Num + std::str::FromStr + From<T> + Copy + num_traits::cast::NumCast,
     <T as std::str::FromStr>::Err: std::fmt::Debug,
 {
-    let to_type = |v: &[u8]| {
-        let str_num = unsafe { from_utf8_unchecked(v) };
-        str_num.parse::<T>().unwrap()
-    };
+    // Numbers too large for `T` fail to parse instead of panicking
+    let to_type = |v: &[u8]| std::str::from_utf8(v).ok()?.parse::<T>().ok();
 
     alt((
-        map(tag("*"), |_| {
-            Repeat::new(cast(0).unwrap(), cast(65535).unwrap())
-        }),
-        map(tag("?"), |_| {
-            Repeat::new(cast(0).unwrap(), cast(1).unwrap())
+        map_opt(tag("*"), |_| Some(Repeat::new(cast(0)?, cast(65535)?))),
+        map_opt(tag("?"), |_| Some(Repeat::new(cast(0)?, cast(1)?))),
+        map_opt(tag("+"), |_| Some(Repeat::new(cast(1)?, cast(65535)?))),
+        map_opt(delimited(tag("{"), digit1, tag("}")), move |v| {
+            let v = to_type(v)?;
+            Some(Repeat::new(v, v))
         }),
-        map(tag("+"), |_| {
-            Repeat::new(cast(1).unwrap(), cast(65535).unwrap())
-        }),
-        map(delimited(tag("{"), digit1, tag("}")), move |v| {
-            let v = to_type(v);
-            Repeat::new(v, v)
-        }),
-        map(
+        map_opt(
             delimited(tag("{"), terminated(digit1, tag(",")), tag("}")),
-            move |v| Repeat::new(to_type(v), cast(65535).unwrap()),
+            move |v| Some(Repeat::new(to_type(v)?, cast(65535)?)),
         ),
-        map(
+        map_opt(
             delimited(tag("{"), preceded(tag(","), digit1), tag("}")),
-            move |v| Repeat::new(cast(0).unwrap(), to_type(v)),
+            move |v| Some(Repeat::new(cast(0)?, to_type(v)?)),
         ),
-        map(
+        map_opt(
             delimited(tag("{"), tuple((digit1, tag(","), digit1)), tag("}")),
-            move |(s, _, e)| Repeat::new(to_type(s), to_type(e)),
+            move |(s, _, e)| Some(Repeat::new(to_type(s)?, to_type(e)?)),
         ),
     ))(input)
 }
@@ -102,7 +103,7 @@ fn alternation(input: &[u8]) -> IResult<&[u8], Node> {
         delimited(tag("["), separated_list(tag("|"), multi_range), tag("]")),
         opt(repeater::<u16>),
     )(input)?;
-    let v = v.into_iter().flatten().map(|v| v).collect();
+    let v = v.into_iter().flatten().collect();
     Ok((rest, Node::Alternation(v, repeat)))
 }
 
@@ -114,40 +115,24 @@ fn set(input: &[u8]) -> IResult<&[u8], Node> {
 
 fn backslash(input: &[u8]) -> IResult<&[u8], Node> {
     alt((
-        map(tag("\\w"), |_| {
-            let ext = (b'A'..=b'Z')
-                .chain(b'a'..=b'z')
-                .chain(b'0'..=b'9')
-                .collect();
-            Node::Range(ext)
-        }),
-        map(tag("\\d"), |_| {
-            let ext = (b'0'..=b'9').collect();
-            Node::Range(ext)
-        }),
-        map(tag("\\s"), |_| Node::Range(vec![b'\t', b'\r', b'\n', b' '])),
-        map(tag("\\W"), |_| {
-            let ext = (b'A'..=b'Z')
-                .chain(b'a'..=b'z')
-                .chain(b'0'..=b'9')
-                .collect();
-            Node::ExRange(ext)
-        }),
-        map(tag("\\D"), |_| {
-            let ext = (b'0'..=b'9').collect();
-            Node::ExRange(ext)
-        }),
-        map(tag("\\S"), |_| {
-            Node::ExRange(vec![b'\t', b'\r', b'\n', b' '])
-        }),
-        map(tag("\\|"), |_| Node::Range(vec![b'|'])),
-        map(terminated(tag("\\"), digit1), |dig: &[u8]| {
-            Node::Ref(std::str::from_utf8(dig).unwrap().parse::<u8>().unwrap())
+        map(tag("\\w"), |_| Node::Range(ALNUM)),
+        map(tag("\\d"), |_| Node::Range(DIGIT)),
+        map(tag("\\s"), |_| Node::Range(SPACE)),
+        map(tag("\\W"), |_| Node::ExRange(ALNUM)),
+        map(tag("\\D"), |_| Node::ExRange(DIGIT)),
+        map(tag("\\S"), |_| Node::ExRange(SPACE)),
+        map(tag("\\|"), |_| Node::Range(CharClass::from_bytes(b"|"))),
+        map_opt(preceded(tag("\\"), digit1), |dig: &[u8]| {
+            std::str::from_utf8(dig)
+                .ok()?
+                .parse::<u8>()
+                .ok()
+                .map(Node::Ref)
         }),
         map(terminated(tag("\\"), alpha1), |v: &[u8]| {
-            Node::Range(v.to_vec())
+            Node::Range(CharClass::from_bytes(v))
         }),
-        map(tag("\\"), |_| Node::Range(vec![b'\\'])),
+        map(tag("\\"), |_| Node::Range(CharClass::from_bytes(b"\\"))),
     ))(input)
 }
 
@@ -163,19 +148,56 @@ fn sequence(input: &[u8]) -> IResult<&[u8], Vec<Node>> {
     many1(alt((text, group, alternation, backslash)))(input)
 }
 
-pub fn parse(re: &[u8]) {
-    sequence(re).expect("");
+/// Deepest group nesting accepted. The parsers recurse for each level,
+/// so deeper patterns are refused up front rather than allowed to
+/// overflow the stack.
+const NEST_MAX: usize = 128;
+
+/// Offset of the first `(` nested deeper than `NEST_MAX`.
+fn too_deep(re: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    let mut iter = re.iter().enumerate();
+    while let Some((i, ch)) = iter.next() {
+        match ch {
+            b'\\' => {
+                iter.next();
+            }
+            b'(' if depth == NEST_MAX => return Some(i),
+            b'(' => depth += 1,
+            b')' => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+    }
+    None
+}
+
+pub fn parse(re: &[u8]) -> Result<Vec<Node>, ParseError> {
+    if let Some(at) = too_deep(re) {
+        return Err(ParseError::new(ErrorKind::TooDeep, at..at + 1, re.to_vec()));
+    }
+    let fail = |rest: &[u8]| {
+        let at = re.len() - rest.len();
+        let span = at..(at + 1).min(re.len());
+        Err(ParseError::new(ErrorKind::Unexpected, span, re.to_vec()))
+    };
+    match sequence(re) {
+        Ok((&[], nodes)) => Ok(nodes),
+        Ok((rest, _)) => fail(rest),
+        Err(nom::Err::Error((rest, _))) | Err(nom::Err::Failure((rest, _))) => fail(rest),
+        Err(nom::Err::Incomplete(_)) => fail(&[]),
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use nom::error::ErrorKind;
 
     #[test]
     fn parse_1() {
         assert_eq!(
             range(b"A-Z"),
-            Ok((&[][..], Node::Range((b'A'..=b'Z').collect::<Vec<_>>())))
+            Ok((&[][..], Node::Range(CharClass::range(b'A', b'Z'))))
         );
     }
     #[test]
@@ -186,8 +208,8 @@ mod test {
                 &[][..],
                 Node::Alternation(
                     vec![
-                        Node::Range(vec![97, 98, 99]),
-                        Node::Range(vec![100, 101, 102])
+                        Node::Range(CharClass::range(b'a', b'c')),
+                        Node::Range(CharClass::range(b'd', b'f'))
                     ],
                     None
                 )
@@ -204,8 +226,8 @@ mod test {
                 Node::Group(
                     vec![Node::Alternation(
                         vec![
-                            Node::Range(vec![97, 98, 99]),
-                            Node::Range(vec![100, 101, 102])
+                            Node::Range(CharClass::range(b'a', b'c')),
+                            Node::Range(CharClass::range(b'd', b'f'))
                         ],
                         None
                     )],
@@ -224,8 +246,8 @@ mod test {
                 Node::Group(
                     vec![Node::Alternation(
                         vec![
-                            Node::Range(vec![97, 98, 99]),
-                            Node::Range(vec![100, 101, 102])
+                            Node::Range(CharClass::range(b'a', b'c')),
+                            Node::Range(CharClass::range(b'd', b'f'))
                         ],
                         None
                     )],
@@ -242,12 +264,9 @@ mod test {
             Ok((
                 &[][..],
                 vec![
-                    Node::Range(vec![97, 98, 99]),
-                    Node::Range(vec![
-                        65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 83,
-                        84, 85, 86, 87, 88, 89, 90
-                    ]),
-                    Node::Range(vec![48, 49, 50, 51, 52, 53, 54, 55, 56, 57])
+                    Node::Range(CharClass::range(b'a', b'c')),
+                    Node::Range(CharClass::range(b'A', b'Z')),
+                    Node::Range(DIGIT)
                 ]
             ))
         );
@@ -282,8 +301,8 @@ mod test {
                 Node::Group(
                     vec![Node::Alternation(
                         vec![
-                            Node::Range(vec![97, 98, 99]),
-                            Node::Range(vec![100, 101, 102])
+                            Node::Range(CharClass::range(b'a', b'c')),
+                            Node::Range(CharClass::range(b'd', b'f'))
                         ],
                         None
                     )],
@@ -298,8 +317,8 @@ mod test {
                 Node::Group(
                     vec![Node::Alternation(
                         vec![
-                            Node::Range(vec![97, 98, 99]),
-                            Node::Range(vec![100, 101, 102])
+                            Node::Range(CharClass::range(b'a', b'c')),
+                            Node::Range(CharClass::range(b'd', b'f'))
                         ],
                         None
                     )],
@@ -314,8 +333,8 @@ mod test {
                 Node::Group(
                     vec![Node::Alternation(
                         vec![
-                            Node::Range(vec![97, 98, 99]),
-                            Node::Range(vec![100, 101, 102])
+                            Node::Range(CharClass::range(b'a', b'c')),
+                            Node::Range(CharClass::range(b'd', b'f'))
                         ],
                         None
                     )],
@@ -330,8 +349,8 @@ mod test {
                 Node::Group(
                     vec![Node::Alternation(
                         vec![
-                            Node::Range(vec![97, 98, 99]),
-                            Node::Range(vec![100, 101, 102])
+                            Node::Range(CharClass::range(b'a', b'c')),
+                            Node::Range(CharClass::range(b'd', b'f'))
                         ],
                         None
                     )],
@@ -346,8 +365,8 @@ mod test {
                 Node::Group(
                     vec![Node::Alternation(
                         vec![
-                            Node::Range(vec![97, 98, 99]),
-                            Node::Range(vec![100, 101, 102])
+                            Node::Range(CharClass::range(b'a', b'c')),
+                            Node::Range(CharClass::range(b'd', b'f'))
                         ],
                         None
                     )],
@@ -362,8 +381,8 @@ mod test {
                 Node::Group(
                     vec![Node::Alternation(
                         vec![
-                            Node::Range(vec![97, 98, 99]),
-                            Node::Range(vec![100, 101, 102])
+                            Node::Range(CharClass::range(b'a', b'c')),
+                            Node::Range(CharClass::range(b'd', b'f'))
                         ],
                         None
                     )],
@@ -380,8 +399,8 @@ mod test {
                 &[][..],
                 Node::Alternation(
                     vec![
-                        Node::Range(vec![97, 98, 99]),
-                        Node::Range(vec![100, 101, 102])
+                        Node::Range(CharClass::range(b'a', b'c')),
+                        Node::Range(CharClass::range(b'd', b'f'))
                     ],
                     Some(Repeat::new(1, 65535))
                 )
@@ -393,8 +412,8 @@ mod test {
                 &[][..],
                 Node::Alternation(
                     vec![
-                        Node::Range(vec![97, 98, 99]),
-                        Node::Range(vec![100, 101, 102])
+                        Node::Range(CharClass::range(b'a', b'c')),
+                        Node::Range(CharClass::range(b'd', b'f'))
                     ],
                     Some(Repeat::new(89, 65535))
                 )
@@ -405,7 +424,7 @@ mod test {
             Ok((
                 &[][..],
                 Node::Alternation(
-                    vec![Node::Range(vec![97, 98, 99]),],
+                    vec![Node::Range(CharClass::range(b'a', b'c')),],
                     Some(Repeat::new(89, 65535))
                 )
             ))
@@ -450,15 +469,11 @@ mod test {
     }
     #[test]
     fn parse_11() {
-        let res: Vec<u8> = (b'A'..=b'Z')
-            .chain(b'a'..=b'z')
-            .chain(b'0'..=b'9')
-            .collect();
         assert_eq!(
             sequence(b"\\wAbcdef"),
             Ok((
                 &[][..],
-                vec![Node::Range(res), Node::Text(b"Abcdef".to_vec())]
+                vec![Node::Range(ALNUM), Node::Text(b"Abcdef".to_vec())]
             ))
         );
     }
@@ -471,8 +486,8 @@ mod test {
                 &[][..],
                 Node::Alternation(
                     vec![
-                        Node::Range(vec![97, 98, 99]),
-                        Node::Range(vec![103, 104, 105, 106, 107])
+                        Node::Range(CharClass::range(b'a', b'c')),
+                        Node::Range(CharClass::range(b'g', b'k'))
                     ],
                     None
                 )
@@ -484,6 +499,35 @@ mod test {
     fn parse_13() {
         println!("{:?}", super::parse(b"([a-z|A-Z])"));
     }
+
+    #[test]
+    fn parse_14() {
+        assert!(repeater::<u16>(b"{99999}").is_err());
+        assert!(repeater::<u16>(b"{1,99999999999}").is_err());
+        assert!(repeater::<u8>(b"*").is_err());
+        assert_eq!(backslash(b"\\12"), Ok((&[][..], Node::Ref(12))));
+        assert!(backslash(b"\\300").is_ok());
+        assert_eq!(
+            super::parse(b"(ab)\\1"),
+            Ok(vec![
+                Node::Group(vec![Node::Text(b"ab".to_vec())], None),
+                Node::Ref(1)
+            ])
+        );
+        let e = super::parse(b"ab{99999}").unwrap_err();
+        assert_eq!(
+            (e.kind, e.span),
+            (crate::error::ErrorKind::Unexpected, 2..3)
+        );
+        assert_eq!(super::parse(b"").unwrap_err().span, 0..0);
+        let deep = |n| "(".repeat(n) + "a" + &")".repeat(n);
+        assert!(super::parse(deep(NEST_MAX).as_bytes()).is_ok());
+        let e = super::parse(deep(100_000).as_bytes()).unwrap_err();
+        assert_eq!(
+            (e.kind, e.span),
+            (crate::error::ErrorKind::TooDeep, 128..129)
+        );
+    }
 }
 /*
 - 07900492482
@@ -1,4 +1,5 @@
-use crate::error::ParseError;
+use crate::class::{CharClass, DIGIT, SPACE, WORD};
+use crate::error::{ErrorKind, Fault, ParseError};
 use std::iter::Peekable;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -36,6 +37,11 @@ const NON_GREEDY: i32 = 1 << 17;
 const PROSSESSIVE: i32 = 1 << 18;
 const CLEAR_FLAGS: i32 = NON_GREEDY - 1;
 
+/// Deepest recursion accepted. Every group and every `|` starts a nested
+/// sequence, so patterns needing more are refused rather than allowed to
+/// overflow the stack.
+const DEPTH_MAX: usize = 1000;
+
 fn char_to_token(ch: &u8) -> Token {
     match ch {
         b'|' => Token::Alt,
@@ -96,36 +102,6 @@ fn token_to_char(token: &Token) -> u8 {
     }
 }
 
-fn is_infinite(node: &Node) -> bool {
-    match node {
-        Node::Repeat(Token::Question) => (1 & INFINITE) != 0,
-        _ => false,
-    }
-}
-
-fn is_non_greedy(node: &Node) -> bool {
-    match node {
-        Node::Repeat(Token::Plus) => (1 & NON_GREEDY) != 0,
-        _ => false,
-    }
-}
-
-fn is_possessive(node: &Node) -> bool {
-    match node {
-        Node::Repeat(Token::Plus) => (1 & PROSSESSIVE) != 0,
-        _ => false,
-    }
-}
-
-fn can_repeat(node: &Node) -> bool {
-    match node {
-        Node::Repeat(Token::Question) => (0 & NON_GREEDY | PROSSESSIVE) != 0,
-        Node::Repeat(Token::Star) => (0 & NON_GREEDY | PROSSESSIVE) != 0,
-        Node::Repeat(Token::Plus) => (1 & NON_GREEDY | PROSSESSIVE) != 0,
-        _ => false,
-    }
-}
-
 struct ParseData {
     ends: Vec<u8>,
     i: usize,
@@ -136,8 +112,8 @@ struct ParseData {
 pub enum Node {
     Edge,
     Text(u8),
-    Charset(Vec<u8>),
-    ExcludeCharset(Vec<u8>),
+    Charset(CharClass),
+    ExcludeCharset(CharClass),
     Repeat(Token),
     FixedRepeat(i32, i32),
     Seq(Vec<Node>),
@@ -166,10 +142,17 @@ where
     piter
 }
 
-fn process_group<I>(mut piter: Peekable<I>, seq: &mut Vec<Node>) -> Peekable<I>
+fn process_group<I>(
+    mut piter: Peekable<I>,
+    seq: &mut Vec<Node>,
+    depth: usize,
+) -> Result<Peekable<I>, Fault>
 where
-    I: Iterator<Item = Token>,
+    I: ExactSizeIterator<Item = Token>,
 {
+    if depth >= DEPTH_MAX {
+        return Err(Fault::new(ErrorKind::TooDeep, piter.len() + 1, piter.len()));
+    }
     let mut group = Vec::new();
     while let Some(ref n) = piter.peek() {
         dbg!(&n);
@@ -189,24 +172,16 @@ where
             _ => break,
         }
     }
-    piter = process_seq(piter, &mut group);
+    piter = process_seq(piter, &mut group, depth + 1)?;
     seq.push(Node::Group(group));
-    piter
-}
-
-fn append_node(parent: &mut Option<Node>, cur: Option<Node>) {
-    if parent.is_none() {
-        *parent = Some(Node::Seq(vec![cur.unwrap()]))
-    } else {
-
-    }
+    Ok(piter)
 }
 
 fn process_range<I>(mut piter: Peekable<I>, end: &mut u8) -> Peekable<I>
 where
     I: Iterator<Item = Token>,
 {
-    while let Some(ref n) = piter.next() {
+    for ref n in piter.by_ref() {
         dbg!(&n);
         match n {
             Token::Character(ch) => *end = *ch,
@@ -227,14 +202,14 @@ where
     while let Some(ref n) = piter.next() {
         dbg!(&n);
         match n {
-            Token::StartAnchor if begin == true => {
+            Token::StartAnchor if begin => {
                 begin = false;
                 set.push(Node::Edge)
             }
             Token::Dash if prev != 0 => {
                 let mut end = 0;
                 piter = process_range(piter, &mut end);
-                set.push(Node::Charset((prev..=end).collect()));
+                set.push(Node::Charset(CharClass::range(prev, end)));
                 prev = 0;
             }
             Token::SetEnd => {
@@ -250,14 +225,21 @@ where
     piter
 }
 
-fn process_select<I>(mut piter: Peekable<I>, seq: &mut Vec<Node>) -> Peekable<I>
+fn process_select<I>(
+    mut piter: Peekable<I>,
+    seq: &mut Vec<Node>,
+    depth: usize,
+) -> Result<Peekable<I>, Fault>
 where
-    I: Iterator<Item = Token>,
+    I: ExactSizeIterator<Item = Token>,
 {
+    if depth >= DEPTH_MAX {
+        return Err(Fault::new(ErrorKind::TooDeep, piter.len() + 1, piter.len()));
+    }
     let mut select = Vec::new();
-    piter = process_seq(piter, &mut select);
+    piter = process_seq(piter, &mut select, depth + 1)?;
     seq.push(Node::Select(select));
-    piter
+    Ok(piter)
 }
 
 fn process_slash<I>(mut piter: Peekable<I>, seq: &mut Vec<Node>) -> Peekable<I>
@@ -267,35 +249,17 @@ where
     while let Some(ref n) = piter.peek() {
         dbg!(&n);
         match n {
-            Token::BackSlash => seq.push(Node::Charset([b'\\'].to_vec())),
-            Token::Character(ch) if *ch == b'n' => seq.push(Node::Charset([b'\n'].to_vec())),
-            Token::Character(ch) if *ch == b'r' => seq.push(Node::Charset([b'\r'].to_vec())),
-            Token::Character(ch) if *ch == b't' => seq.push(Node::Charset([b'\t'].to_vec())),
-            Token::Character(ch) if *ch == b'd' => seq.push(Node::Charset((b'0'..=b'9').collect())),
-            Token::Character(ch) if *ch == b'D' => {
-                seq.push(Node::ExcludeCharset((b'0'..=b'9').collect()))
-            }
-            Token::Character(ch) if *ch == b's' => seq.push(Node::Charset([b'\t', b' '].to_vec())),
-            Token::Character(ch) if *ch == b'w' => {
-                let mut v = (b'A'..=b'Z')
-                    .take(26)
-                    .chain((b'a'..=b'z').take(26))
-                    .chain((b'0'..=b'9').take(10))
-                    .collect::<Vec<_>>();
-                v.push(b'_');
-                seq.push(Node::Charset(v));
-            }
-            Token::Character(ch) if *ch == b'W' => {
-                let mut v = (b'A'..=b'Z')
-                    .take(26)
-                    .chain((b'a'..=b'z').take(26))
-                    .chain((b'0'..=b'9').take(10))
-                    .collect::<Vec<_>>();
-                v.push(b'_');
-                seq.push(Node::ExcludeCharset(v));
-            }
+            Token::BackSlash => seq.push(Node::Charset(CharClass::from_bytes(b"\\"))),
+            Token::Character(b'n') => seq.push(Node::Charset(CharClass::from_bytes(b"\n"))),
+            Token::Character(b'r') => seq.push(Node::Charset(CharClass::from_bytes(b"\r"))),
+            Token::Character(b't') => seq.push(Node::Charset(CharClass::from_bytes(b"\t"))),
+            Token::Character(b'd') => seq.push(Node::Charset(DIGIT)),
+            Token::Character(b'D') => seq.push(Node::ExcludeCharset(DIGIT)),
+            Token::Character(b's') => seq.push(Node::Charset(SPACE)),
+            Token::Character(b'w') => seq.push(Node::Charset(WORD)),
+            Token::Character(b'W') => seq.push(Node::ExcludeCharset(WORD)),
             Token::Number(n) => seq.push(Node::Ref(*n)),
-            Token::Character(ch) => seq.push(Node::Charset(vec![*ch])),
+            Token::Character(ch) => seq.push(Node::Charset(CharClass::from_bytes(&[*ch]))),
             _ => break, //panic!("Unexpected token at slash {:?}", n),
         }
         piter.next();
@@ -303,9 +267,13 @@ where
     piter
 }
 
-fn process_seq<I>(mut piter: Peekable<I>, seq: &mut Vec<Node>) -> Peekable<I>
+fn process_seq<I>(
+    mut piter: Peekable<I>,
+    seq: &mut Vec<Node>,
+    depth: usize,
+) -> Result<Peekable<I>, Fault>
 where
-    I: Iterator<Item = Token>,
+    I: ExactSizeIterator<Item = Token>,
 {
     let mut seq_p = Vec::new();
     while let Some(ref n) = piter.next() {
@@ -314,14 +282,14 @@ where
             Token::StartAnchor => seq_p.push(Node::Edge),
             Token::SetBegin => piter = process_set(piter, &mut seq_p),
             Token::BackSlash => piter = process_slash(piter, &mut seq_p),
-            Token::GroupBegin => piter = process_group(piter, &mut seq_p),
-            Token::Alt => piter = process_select(piter, &mut seq_p),
+            Token::GroupBegin => piter = process_group(piter, &mut seq_p, depth)?,
+            Token::Alt => piter = process_select(piter, &mut seq_p, depth)?,
             Token::RepeatBegin if !seq_p.is_empty() => piter = process_repeat(piter, &mut seq_p),
             c @ Token::Question | c @ Token::Plus | c @ Token::Star if !seq_p.is_empty() => {
                 seq_p.push(Node::Repeat(c.clone()))
             }
             Token::Any => {
-                seq_p.push(Node::Charset((32u8..126u8).take(94).collect()));
+                seq_p.push(Node::Charset(CharClass::range(b' ', b'}')));
             }
             Token::GroupEnd => (),
             Token::RepeatEnd => (),
@@ -331,26 +299,42 @@ where
             Token::Quote => seq_p.push(Node::Text(b'"')),
             Token::Character(ch) => seq_p.push(Node::Text(*ch)),
             Token::Number(n) => seq_p.push(Node::Text(*n)),
-            Token::Colon => seq_p.push(Node::Text(b':')),
-            _ => panic!("Unexpected pattern in sequence {:?}", n),
+            tok @ Token::Colon
+            | tok @ Token::Comma
+            | tok @ Token::Equals
+            | tok @ Token::Not
+            | tok @ Token::GreaterThan
+            | tok @ Token::Tick
+            | tok @ Token::BackTick => seq_p.push(Node::Text(token_to_char(tok))),
+            // A quantifier with nothing before it
+            _ => {
+                let kind = ErrorKind::DanglingQuantifier;
+                return Err(Fault::new(kind, piter.len() + 1, piter.len()));
+            }
         }
     }
     seq.extend(seq_p);
-    piter
+    Ok(piter)
 }
 
 pub fn parse(re: Vec<u8>) -> Result<Vec<Node>, ParseError> {
-    let re = pre_parse(re);
+    let shift = match re.first() {
+        Some(b'^') | None => 0,
+        Some(_) => 1,
+    };
+    let full = pre_parse(re.clone());
     // Lex the whole regex
-    let mut iter_chars = re.iter().map(|ch| char_to_token(&ch)).peekable();
+    let iter_chars = full.iter().map(char_to_token).peekable();
     let mut seq = Vec::new();
-    let top = process_seq(iter_chars, &mut seq);
-    Ok(seq)
+    match process_seq(iter_chars, &mut seq, 0) {
+        Ok(_) => Ok(seq),
+        Err(fault) => Err(fault.locate(full.len(), shift, re)),
+    }
 }
 
 fn pre_parse(mut s: Vec<u8>) -> Vec<u8> {
-    const BEGIN: &[u8] = &[b'^'];
-    const END: &[u8] = &[b'$'];
+    const BEGIN: &[u8] = b"^";
+    const END: &[u8] = b"$";
     if let Some(&b) = s.first() {
         if b != b'^' {
             s.splice(0..0, BEGIN.iter().cloned());
@@ -660,7 +644,23 @@ mod tests {
     #[test]
     fn parse_39() {
         let regex = b"(a-z|A-Z|:;+)[cd]{2}\\1";
-        println!("{:?}", super::parse(b"(a-z|A-Z|:;+)[cd]{2}\\1".to_vec()));
+        println!("{:?}", super::parse(regex.to_vec()));
     }
 
+    #[test]
+    fn parse_40() {
+        use crate::error::ErrorKind;
+        let err = |re: &[u8]| {
+            let e = super::parse(re.to_vec()).unwrap_err();
+            (e.kind, e.span)
+        };
+        assert!(super::parse(b"a,b='c'>!`".to_vec()).is_ok());
+        assert_eq!(err(b"(*a)"), (ErrorKind::DanglingQuantifier, 1..2));
+        assert_eq!(err(b"ab|+c"), (ErrorKind::DanglingQuantifier, 3..4));
+        let alts = "a|".repeat(super::DEPTH_MAX - 1) + "a";
+        assert!(super::parse(alts.into_bytes()).is_ok());
+        let groups = "(".repeat(100_000);
+        let e = super::parse(groups.into_bytes()).unwrap_err();
+        assert_eq!((e.kind, e.offset()), (ErrorKind::TooDeep, super::DEPTH_MAX));
+    }
 }
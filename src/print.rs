@@ -0,0 +1,167 @@
+use crate::ast::{GroupKind, Node};
+use crate::class::CharClass;
+
+/// Bytes with a meaning of their own outside character sets.
+const META: &[u8] = br"\^$.|?*+()[]{}";
+
+/// Bytes with a meaning of their own inside character sets.
+const SET_META: &[u8] = br"\^-|[]";
+
+/// Write `ch` so that it reads back as itself, escaping it if it is in
+/// `meta`.
+fn byte(out: &mut Vec<u8>, ch: u8, meta: &[u8]) {
+    match ch {
+        b'\n' => out.extend_from_slice(br"\n"),
+        b'\r' => out.extend_from_slice(br"\r"),
+        b'\t' => out.extend_from_slice(br"\t"),
+        _ if meta.contains(&ch) => out.extend_from_slice(&[b'\\', ch]),
+        _ => out.push(ch),
+    }
+}
+
+/// Write the members of a set in ascending order, shortening runs of three
+/// or more consecutive bytes to a range.
+fn members(out: &mut Vec<u8>, class: &CharClass) {
+    for range in class.ranges() {
+        if range.end() - range.start() >= 2 {
+            byte(out, *range.start(), SET_META);
+            out.push(b'-');
+            byte(out, *range.end(), SET_META);
+        } else {
+            range.for_each(|ch| byte(out, ch, SET_META));
+        }
+    }
+}
+
+/// True if a quantifier written after the node applies to all of it.
+fn is_atom(node: &Node) -> bool {
+    match node {
+        Node::Text(bytes) => bytes.len() == 1,
+        Node::Any | Node::Charset { .. } | Node::Group(..) | Node::Ref(_) => true,
+        Node::Seq(nodes) => matches!(&nodes[..], [node] if is_atom(node)),
+        Node::Empty | Node::Start | Node::End | Node::Select(_) | Node::Repeat { .. } => false,
+    }
+}
+
+fn quantifier(out: &mut Vec<u8>, min: usize, max: Option<usize>) {
+    match (min, max) {
+        (0, None) => out.push(b'*'),
+        (1, None) => out.push(b'+'),
+        (0, Some(1)) => out.push(b'?'),
+        (min, None) => out.extend_from_slice(format!("{{{},}}", min).as_bytes()),
+        (min, Some(max)) if min == max => out.extend_from_slice(format!("{{{}}}", min).as_bytes()),
+        (min, Some(max)) => out.extend_from_slice(format!("{{{},{}}}", min, max).as_bytes()),
+    }
+}
+
+fn write(out: &mut Vec<u8>, node: &Node) {
+    match node {
+        Node::Empty => (),
+        Node::Start => out.push(b'^'),
+        Node::End => out.push(b'$'),
+        Node::Any => out.push(b'.'),
+        Node::Text(bytes) => bytes.iter().for_each(|&ch| byte(out, ch, META)),
+        // `[]` doesn't parse, so an empty set is written as the whole
+        // alphabet, negated the other way
+        Node::Charset { class, negated } if class.is_empty() => match negated {
+            true => out.extend_from_slice(br"[\s\S]"),
+            false => out.extend_from_slice(br"[^\s\S]"),
+        },
+        Node::Charset { class, negated } => {
+            out.push(b'[');
+            if *negated {
+                out.push(b'^');
+            }
+            members(out, class);
+            out.push(b']');
+        }
+        Node::Seq(nodes) => {
+            for node in nodes {
+                match node {
+                    Node::Select(_) => {
+                        out.extend_from_slice(b"(?:");
+                        write(out, node);
+                        out.push(b')');
+                    }
+                    _ => write(out, node),
+                }
+            }
+        }
+        Node::Select(nodes) => {
+            for (i, node) in nodes.iter().enumerate() {
+                if i > 0 {
+                    out.push(b'|');
+                }
+                write(out, node);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            if is_atom(node) {
+                write(out, node);
+            } else {
+                out.extend_from_slice(b"(?:");
+                write(out, node);
+                out.push(b')');
+            }
+            quantifier(out, *min, *max);
+        }
+        Node::Group(node, kind) => {
+            out.extend_from_slice(match kind {
+                GroupKind::Capture(_) => b"(",
+                GroupKind::NonCapture => b"(?:",
+                GroupKind::Lookahead { negative: false } => b"(?=",
+                GroupKind::Lookahead { negative: true } => b"(?!",
+                GroupKind::Lookbehind { negative: false } => b"(?<=",
+                GroupKind::Lookbehind { negative: true } => b"(?<!",
+                GroupKind::Atomic => b"(?>",
+            });
+            write(out, node);
+            out.push(b')');
+        }
+        Node::Ref(n) => out.extend_from_slice(format!("\\{}", n).as_bytes()),
+    }
+}
+
+/// Write a pattern tree back out as a pattern.
+///
+/// Parsing the result gives back the same tree for any tree that
+/// [`parse`](crate::parse) produced. Named groups come out as plain
+/// capturing ones, and lazy or possessive quantifiers as greedy ones,
+/// since the tree doesn't keep the difference.
+pub fn print(node: &Node) -> Vec<u8> {
+    let mut out = Vec::new();
+    write(&mut out, node);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn print_a() {
+        for re in crate::CORPUS {
+            let ast = parse(re.as_bytes()).unwrap();
+            assert_eq!(parse(&print(&ast)).as_ref(), Ok(&ast), "{}", re);
+        }
+        assert!(parse(b"\"(?:[^\"\\]++|\\.)*+\"").is_err());
+    }
+
+    #[test]
+    fn print_b() {
+        let print_re = |re: &[u8]| String::from_utf8(print(&parse(re).unwrap())).unwrap();
+        assert_eq!(print_re(br"a\{b\.c"), r"^a\{b\.c$");
+        assert_eq!(print_re(br"[\]\-^\|a-cx]"), r"^[\-\]\^a-cx\|]$");
+        assert_eq!(print_re(br"(?<n>a)+?\12{3,}"), r"^(a)+\12{3,}$");
+        assert_eq!(print_re(b"\t(?!x)(?<=y)(?>z)"), r"^\t(?!x)(?<=y)(?>z)$");
+        assert_eq!(print_re(br".[\s\S][^\D\d]"), r"^.[\s\S][^\s\S]$");
+        let ab = Node::Repeat {
+            node: Box::new(Node::Text(b"ab".to_vec())),
+            min: 0,
+            max: Some(3),
+        };
+        let select = Node::Select(vec![Node::Text(b"c".to_vec()), Node::Empty]);
+        assert_eq!(print(&Node::Seq(vec![ab, select])), b"(?:ab){0,3}(?:c|)");
+    }
+}
@@ -0,0 +1,544 @@
+use crate::bigint::BigUint;
+use crate::enumerate::Enumerator;
+use crate::expr::{Captures, Expr};
+use crate::matcher::{plain, LONGEST, STEPS};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// How many strings each sub-expression yields in enumeration order,
+/// counting every way of producing a string. With a cap, anything at or
+/// above it is reported as the cap, which keeps unbounded repeats cheap
+/// when only positions below the cap matter.
+pub(crate) struct Counts {
+    cap: Option<BigUint>,
+    memo: HashMap<*const Expr, BigUint>,
+}
+
+impl Counts {
+    pub fn new(cap: Option<BigUint>) -> Self {
+        Self {
+            cap,
+            memo: HashMap::new(),
+        }
+    }
+
+    fn clamp(&self, n: BigUint) -> BigUint {
+        match &self.cap {
+            Some(cap) if n > *cap => cap.clone(),
+            _ => n,
+        }
+    }
+
+    pub fn add(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        self.clamp(a + b)
+    }
+
+    pub fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        self.clamp(a * b)
+    }
+
+    pub fn of(&mut self, expr: &Expr) -> BigUint {
+        if let Some(n) = self.memo.get(&(expr as *const Expr)) {
+            return n.clone();
+        }
+        let n = match expr {
+            Expr::Empty | Expr::Byte(_) | Expr::Ref(_) => BigUint::one(),
+            Expr::Group(_, inner) => self.of(inner),
+            Expr::Class(set) => self.clamp(BigUint::from(set.len() as u64)),
+            Expr::Seq(exprs) => exprs.iter().fold(BigUint::one(), |n, e| {
+                let c = self.of(e);
+                self.mul(&n, &c)
+            }),
+            Expr::Alt(exprs) => exprs.iter().fold(BigUint::zero(), |n, e| {
+                let c = self.of(e);
+                self.add(&n, &c)
+            }),
+            Expr::Repeat(_, min, max) if min > max => BigUint::zero(),
+            Expr::Repeat(inner, min, max) => {
+                let c = self.of(inner);
+                self.span(&c, *min, *max + 1)
+            }
+        };
+        self.memo.insert(expr, n.clone());
+        n
+    }
+
+    /// Strings from `from` up to but not including `to` repetitions of
+    /// something with `c` strings, the sum of `c^k` over that range.
+    pub fn span(&self, c: &BigUint, from: usize, to: usize) -> BigUint {
+        if from >= to {
+            return BigUint::zero();
+        }
+        let (sum, _) = self.geometric(c, to - from);
+        self.mul(&self.pow(c, from), &sum)
+    }
+
+    pub fn pow(&self, c: &BigUint, k: usize) -> BigUint {
+        self.geometric(c, k).1
+    }
+
+    /// `1 + c + .. + c^(n-1)` and `c^n`, by halving `n` so that even a
+    /// 65536-fold repeat takes a few dozen multiplications.
+    fn geometric(&self, c: &BigUint, n: usize) -> (BigUint, BigUint) {
+        if n == 0 {
+            (BigUint::zero(), BigUint::one())
+        } else if n % 2 == 1 {
+            let (sum, p) = self.geometric(c, n - 1);
+            (self.add(&sum, &p), self.mul(&p, c))
+        } else {
+            let (sum, p) = self.geometric(c, n / 2);
+            let next = self.add(&BigUint::one(), &p);
+            (self.mul(&sum, &next), self.mul(&p, &p))
+        }
+    }
+}
+
+/// The string at `index` in enumeration order, counting from zero, or
+/// `None` past the end. Nothing before it is generated.
+pub fn nth(expr: &Expr, index: &BigUint) -> Option<Vec<u8>> {
+    Enumerator::starting_at(expr, index).next()
+}
+
+/// Positions of slice `index` of `shards` slices of enumeration order,
+/// counting from zero, or `None` if there is no such slice. The slices
+/// differ in size by at most one string, and together they hold every
+/// position once, so enumerating each with [`Enumerator::between`] gives
+/// every string of the pattern exactly as often as [`Enumerator`] does.
+///
+/// This counts every string, so it also gives `None` for a pattern with
+/// unbounded repeats, whose count is astronomical; [`Expr::bounded`]
+/// limits them first.
+pub fn shard(expr: &Expr, index: u64, shards: u64) -> Option<Range<BigUint>> {
+    if index >= shards || expr.max_len().is_none() {
+        return None;
+    }
+    let total = Counts::new(None).of(expr);
+    let at = |i: u64| {
+        (&total * &BigUint::from(i))
+            .div_rem(&BigUint::from(shards))
+            .0
+    };
+    Some(at(index)..at(index + 1))
+}
+
+/// Position of `s` in enumeration order, or `None` if the pattern doesn't
+/// match it. When a pattern can produce the same string several ways, this
+/// is its first occurrence, so `nth(rank(s)) == s` always holds.
+///
+/// Positions after a long unbounded repeat are astronomically large, and
+/// computing them costs time in proportion.
+///
+/// Patterns with backreferences are searched by backtracking instead,
+/// which gives up with `None` on strings longer than 2048 bytes or after
+/// about a million steps.
+pub fn rank(expr: &Expr, s: &[u8]) -> Option<BigUint> {
+    if expr.has_refs() {
+        if s.len() > LONGEST {
+            return None;
+        }
+        let mut search = Search {
+            s,
+            ranker: Ranker {
+                s,
+                counts: Counts::new(None),
+                memo: HashMap::new(),
+            },
+            groups: Captures::default(),
+            steps: STEPS,
+        };
+        return search.walk(expr, 0, &mut |_, end, i| Some(i).filter(|_| end == s.len()));
+    }
+    let mut ranker = Ranker {
+        s,
+        counts: Counts::new(None),
+        memo: HashMap::new(),
+    };
+    ranker.ends(expr, 0)[s.len()].clone()
+}
+
+/// Smallest position for each end offset of the input, if any.
+type Ends = Vec<Option<BigUint>>;
+
+struct Ranker<'s> {
+    s: &'s [u8],
+    counts: Counts,
+    memo: HashMap<(*const Expr, usize), Rc<Ends>>,
+}
+
+impl<'s> Ranker<'s> {
+    fn start(&self, pos: usize) -> Ends {
+        let mut ends = vec![None; self.s.len() + 1];
+        ends[pos] = Some(BigUint::zero());
+        ends
+    }
+
+    /// For every `end`, the position within `expr` of the first string it
+    /// produces that equals `s[pos..end]`.
+    fn ends(&mut self, expr: &Expr, pos: usize) -> Rc<Ends> {
+        if let Some(ends) = self.memo.get(&(expr as *const Expr, pos)) {
+            return ends.clone();
+        }
+        let mut ends = vec![None; self.s.len() + 1];
+        match expr {
+            // Patterns with backreferences are ranked by `Search` instead
+            Expr::Empty | Expr::Ref(_) => ends[pos] = Some(BigUint::zero()),
+            Expr::Group(_, inner) => ends = self.ends(inner, pos).to_vec(),
+            Expr::Byte(ch) => {
+                if self.s.get(pos) == Some(ch) {
+                    ends[pos + 1] = Some(BigUint::zero());
+                }
+            }
+            Expr::Class(set) => {
+                if let Some(i) = self.s.get(pos).and_then(|&ch| set.index(ch)) {
+                    ends[pos + 1] = Some(BigUint::from(i as u64));
+                }
+            }
+            Expr::Seq(exprs) => {
+                ends = self.start(pos);
+                for e in exprs {
+                    ends = self.extend(&ends, e);
+                }
+            }
+            Expr::Alt(exprs) => {
+                // Earlier branches come first, whatever the position inside
+                let mut offset = BigUint::zero();
+                for e in exprs {
+                    let found = self.ends(e, pos);
+                    for (slot, i) in ends.iter_mut().zip(found.iter()) {
+                        if let (None, Some(i)) = (&slot, i) {
+                            *slot = Some(&offset + i);
+                        }
+                    }
+                    if ends.iter().any(Option::is_none) {
+                        offset += &self.counts.of(e);
+                    }
+                }
+            }
+            Expr::Repeat(inner, min, max) => {
+                // Fewer repetitions come first, so each end takes the
+                // smallest count that reaches it
+                let c = self.counts.of(inner);
+                let mut layer = self.start(pos);
+                let mut k = 0;
+                loop {
+                    if k >= *min {
+                        let offset = self.counts.span(&c, *min, k);
+                        for (slot, i) in ends.iter_mut().zip(&layer) {
+                            if let (None, Some(i)) = (&slot, i) {
+                                *slot = Some(&offset + i);
+                            }
+                        }
+                    }
+                    if k >= *max {
+                        break;
+                    }
+                    let next = self.extend(&layer, inner);
+                    // Once the reachable ends stop changing, more
+                    // repetitions can't reach anything new
+                    let same = next
+                        .iter()
+                        .zip(&layer)
+                        .all(|(a, b)| a.is_some() == b.is_some());
+                    if next.iter().all(Option::is_none) || (k >= *min && same) {
+                        break;
+                    }
+                    layer = next;
+                    k += 1;
+                }
+            }
+        }
+        let ends = Rc::new(ends);
+        self.memo.insert((expr, pos), ends.clone());
+        ends
+    }
+
+    /// Follow each partial match in `layer` with `expr`, keeping the
+    /// smallest combined position per end.
+    fn extend(&mut self, layer: &[Option<BigUint>], expr: &Expr) -> Ends {
+        // Only needed past a non-zero position, and costly to find for a
+        // long unbounded repeat
+        let mut c = None;
+        let mut next: Ends = vec![None; layer.len()];
+        for (mid, at) in layer.iter().enumerate() {
+            let at = match at {
+                Some(at) => at,
+                None => continue,
+            };
+            let found = self.ends(expr, mid);
+            for (slot, i) in next.iter_mut().zip(found.iter()) {
+                if let Some(i) = i {
+                    let candidate = if at.is_zero() {
+                        i.clone()
+                    } else {
+                        let c = c.get_or_insert_with(|| self.counts.of(expr));
+                        &(at * c) + i
+                    };
+                    if slot.as_ref().is_none_or(|best| candidate < *best) {
+                        *slot = Some(candidate);
+                    }
+                }
+            }
+        }
+        next
+    }
+}
+
+/// Called with the end offset and position of each match, in enumeration
+/// order, until it returns a result.
+type Found<'f, 's> = &'f mut dyn FnMut(&mut Search<'s>, usize, BigUint) -> Option<BigUint>;
+
+/// Depth-first search through the ways a pattern can produce `s`, in
+/// enumeration order, for patterns with backreferences. What those match
+/// depends on earlier choices, not just the position, so the per-position
+/// tables of `Ranker` only apply to the plain parts.
+struct Search<'s> {
+    s: &'s [u8],
+    /// For plain parts, which match the same whatever came before.
+    ranker: Ranker<'s>,
+    groups: Captures,
+    /// Steps left before giving up.
+    steps: usize,
+}
+
+impl<'s> Search<'s> {
+    fn walk(&mut self, expr: &Expr, pos: usize, found: Found<'_, 's>) -> Option<BigUint> {
+        if self.steps == 0 {
+            return None;
+        }
+        self.steps -= 1;
+        match expr {
+            Expr::Empty => found(self, pos, BigUint::zero()),
+            Expr::Byte(ch) if self.s.get(pos) == Some(ch) => found(self, pos + 1, BigUint::zero()),
+            Expr::Byte(_) => None,
+            Expr::Class(set) => match self.s.get(pos).and_then(|&ch| set.index(ch)) {
+                Some(i) => found(self, pos + 1, BigUint::from(i as u64)),
+                _ => None,
+            },
+            Expr::Seq(exprs) => {
+                let row = exprs.iter().collect::<Vec<_>>();
+                self.row(&row, pos, BigUint::zero(), found)
+            }
+            Expr::Alt(exprs) => {
+                let mut offset = BigUint::zero();
+                for e in exprs {
+                    let result = self.walk(e, pos, &mut |search, end, i| {
+                        found(search, end, &offset + &i)
+                    });
+                    if result.is_some() {
+                        return result;
+                    }
+                    offset += &self.ranker.counts.of(e);
+                }
+                None
+            }
+            // Plain repeats take their positions from the ranker rather than
+            // trying each way of splitting the bytes between iterations.
+            // Smaller positions come first whatever follows, so each end
+            // is tried once, in order of its position
+            Expr::Repeat(..) if plain(expr) => {
+                let ends = self.ranker.ends(expr, pos);
+                let mut ends = ends
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(end, i)| Some((i.clone()?, end)))
+                    .collect::<Vec<_>>();
+                ends.sort();
+                ends.into_iter().find_map(|(i, end)| found(self, end, i))
+            }
+            Expr::Repeat(inner, min, max) => {
+                // The first match never needs more repetitions than there
+                // are bytes left beyond the minimum, as extra empty ones
+                // only move it later
+                let c = self.ranker.counts.of(inner);
+                let most = (*max).min(min + self.s.len() - pos);
+                for k in *min..=most {
+                    let offset = self.ranker.counts.span(&c, *min, k);
+                    let row = vec![&**inner; k];
+                    let result = self.row(&row, pos, BigUint::zero(), &mut |search, end, i| {
+                        found(search, end, &offset + &i)
+                    });
+                    if result.is_some() {
+                        return result;
+                    }
+                }
+                None
+            }
+            Expr::Group(n, inner) => self.walk(inner, pos, &mut |search, end, i| {
+                let before = search.groups.get(*n);
+                search.groups.set(*n, Some(pos..end));
+                let result = found(search, end, i);
+                search.groups.set(*n, before);
+                result
+            }),
+            Expr::Ref(n) => {
+                let span = self.groups.get(*n).unwrap_or(0..0);
+                let len = span.len();
+                if self.s[pos..].starts_with(&self.s[span]) {
+                    found(self, pos + len, BigUint::zero())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Match a row of expressions one after another, combining their
+    /// positions with the last turning fastest.
+    fn row(
+        &mut self,
+        row: &[&Expr],
+        pos: usize,
+        at: BigUint,
+        found: Found<'_, 's>,
+    ) -> Option<BigUint> {
+        match row.split_first() {
+            None => found(self, pos, at),
+            Some((first, rest)) => self.walk(first, pos, &mut |search, end, i| {
+                let at = if at.is_zero() {
+                    i
+                } else {
+                    &(&at * &search.ranker.counts.of(first)) + &i
+                };
+                search.row(rest, end, at, found)
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::estimate::estimate;
+    use crate::expr::compile;
+    use crate::parse;
+
+    fn expr(re: &str) -> Expr {
+        compile(&parse(re.as_bytes()).unwrap())
+    }
+
+    /// nth and rank agree with plain enumeration over the first strings.
+    fn check(re: &str, limit: usize) {
+        let expr = expr(re);
+        let all = Enumerator::new(&expr).take(limit).collect::<Vec<_>>();
+        for (i, s) in all.iter().enumerate() {
+            let index = BigUint::from(i as u64);
+            assert_eq!(nth(&expr, &index).as_ref(), Some(s), "{} at {}", re, i);
+            let first = all.iter().position(|t| t == s).unwrap();
+            assert_eq!(
+                rank(&expr, s),
+                Some(BigUint::from(first as u64)),
+                "{} {:?}",
+                re,
+                s
+            );
+        }
+        if all.len() < limit {
+            assert_eq!(nth(&expr, &BigUint::from(all.len() as u64)), None);
+        }
+    }
+
+    #[test]
+    fn rank_a() {
+        check("abc", 10);
+        check("ab|c|", 10);
+        check("(a|bc){1,2}", 10);
+        check("a?b?", 10);
+        check("((x|y){2}z?){2}", 100);
+        check("a[^ -~]|b[^ -~]?c", 10);
+    }
+
+    #[test]
+    fn rank_b() {
+        check("(a|a)b{0,3}", 20);
+        check("(a|ab)(c|bcd)(d*)", 60);
+        check("(a?){2,3}", 60);
+        check(r"\w+@x", 200);
+        check("(a|b*)*c", 60);
+    }
+
+    #[test]
+    fn rank_e() {
+        check(r"([ab])x\1", 10);
+        check(r"(a|b){2}\1", 10);
+        check(r"(?:(a)|b)+\1", 60);
+        check(r"(a|aa)*-\1", 60);
+        check(r"<([a-c]{1,2})>\1</\1>", 20);
+        assert_eq!(rank(&expr(r"([ab])x\1"), b"axb"), None);
+        let long = expr(r"(a{1024})\1");
+        assert_eq!(rank(&long, &[b'a'; 2048]), Some(BigUint::zero()));
+        assert_eq!(rank(&expr(r"(a*)\1"), &[b'a'; LONGEST + 2]), None);
+        assert_eq!(rank(&expr(r"(a*)*b\1"), &[b'a'; 40]), None);
+    }
+
+    #[test]
+    fn rank_c() {
+        let expr = expr("[a-z0-9_-]{6,18}");
+        let total = (6..=18).fold(BigUint::zero(), |total, k| {
+            let mut n = BigUint::one();
+            (0..k).for_each(|_| n = &n * &BigUint::from(38));
+            &total + &n
+        });
+        assert_eq!(total.to_string(), "28034099579103634892205993664");
+        let end = &total - &BigUint::one();
+        assert_eq!(nth(&expr, &end), Some(vec![b'z'; 18]));
+        assert_eq!(nth(&expr, &total), None);
+        assert_eq!(rank(&expr, &[b'z'; 18]), Some(end));
+        assert_eq!(rank(&expr, b"abc"), None);
+        let s = b"hello_world-2024";
+        let index = rank(&expr, s).unwrap();
+        assert_eq!(nth(&expr, &index), Some(s.to_vec()));
+    }
+
+    #[test]
+    fn rank_d() {
+        let expr = expr(r"\w+([-+.]\w+)*@\w+([-.]\w+)*\.\w+");
+        let mut strings = Enumerator::new(&expr);
+        assert_eq!(nth(&expr, &BigUint::from(63)), strings.nth(63));
+        let far = nth(&expr, &BigUint::from(1 << 40)).unwrap();
+        assert_eq!(far.iter().filter(|&&ch| ch == b'@').count(), 1);
+    }
+
+    #[test]
+    fn shard_a() {
+        for re in &[
+            "(a|bc(d|e){0,2}){1,3}\\1",
+            "((x|y){2}z?){2}",
+            "(a|a)b?",
+            "",
+            "[^\\s\\S]",
+        ] {
+            let expr = expr(re);
+            let all = Enumerator::new(&expr).collect::<Vec<_>>();
+            for shards in 1..=7 {
+                let mut joined = Vec::new();
+                for index in 0..shards {
+                    let range = shard(&expr, index, shards).unwrap();
+                    let size = (&range.end - &range.start).to_u64().unwrap() as usize;
+                    assert!(size.abs_diff(all.len() / shards as usize) <= 1, "{}", re);
+                    joined.extend(Enumerator::between(&expr, &range.start, &range.end));
+                }
+                assert_eq!(joined, all, "{} in {}", re, shards);
+            }
+        }
+        let expr = expr("[a-z0-9_-]{6,18}");
+        let last = shard(&expr, 9, 10).unwrap();
+        let mut strings = Enumerator::between(&expr, &last.start, &last.end);
+        assert_eq!(strings.next(), nth(&expr, &last.start));
+        assert_eq!(
+            shard(&expr, 0, 1).unwrap().end.to_string(),
+            "28034099579103634892205993664"
+        );
+        assert_eq!(shard(&expr, 10, 10), None);
+        assert_eq!(shard(&expr, 0, 0), None);
+        let nested = self::expr(r"\w+([-+.]\w+)*");
+        assert_eq!(shard(&nested, 0, 2), None);
+        let bounded = nested.bounded(1);
+        let (first, second) = (
+            shard(&bounded, 0, 2).unwrap(),
+            shard(&bounded, 1, 2).unwrap(),
+        );
+        assert_eq!(first.end, second.start);
+        assert_eq!(second.end, estimate(&bounded).unwrap().strings);
+    }
+}
@@ -1,32 +1,83 @@
-use crate::error::ParseError;
+use crate::ast::{self, GroupKind};
+use crate::class::{CharClass, DIGIT, SPACE, WORD};
+use crate::error::{ErrorKind, Fault, ParseError};
 use std::iter::Peekable;
 
-const INFINITE: i32 = 1 << 16;
+pub const INFINITE: i32 = 1 << 16;
 const REPEAT_MAX: i32 = INFINITE - 1;
-const NON_GREEDY: i32 = 1 << 17;
-const PROSSESSIVE: i32 = 1 << 18;
-const CLEAR_FLAGS: i32 = NON_GREEDY - 1;
 
 #[derive(Clone, PartialEq, Debug)]
-struct Repeat {
-    min: i32,
-    max: i32,
+pub struct Repeat {
+    pub min: i32,
+    pub max: i32,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum Node {
+pub enum Node {
     Edge(bool),
+    Any(Option<Repeat>),
     Text(u8, Option<Repeat>),
-    Charset(Vec<u8>, bool, Option<Repeat>),
+    Charset(CharClass, bool, Option<Repeat>),
     Seq(Vec<Node>, Option<Repeat>),
-    Group(Vec<Node>, usize, Option<Repeat>),
+    Group(Vec<Node>, GroupKind, Option<Repeat>),
     Select(Vec<Node>, Option<Repeat>),
     Ref(usize),
 }
 
+impl Node {
+    /// The same pattern as a public `ast::Node`, with repeats as nodes of
+    /// their own.
+    pub fn lower(&self) -> ast::Node {
+        let (node, repeat) = match self {
+            Node::Edge(true) => (ast::Node::Start, &None),
+            Node::Edge(false) => (ast::Node::End, &None),
+            Node::Any(r) => (ast::Node::Any, r),
+            Node::Text(ch, r) => (ast::Node::Text(vec![*ch]), r),
+            Node::Charset(class, include, r) => (
+                ast::Node::Charset {
+                    class: *class,
+                    negated: !include,
+                },
+                r,
+            ),
+            Node::Seq(nodes, r) => (ast::Node::Seq(lower_all(nodes)), r),
+            Node::Group(nodes, kind, r) => {
+                let inner = match &nodes[..] {
+                    [node] => node.lower(),
+                    _ => ast::Node::Seq(lower_all(nodes)),
+                };
+                (ast::Node::Group(Box::new(inner), *kind), r)
+            }
+            Node::Select(nodes, r) => (ast::Node::Select(lower_all(nodes)), r),
+            Node::Ref(n) => (ast::Node::Ref(*n), &None),
+        };
+        match repeat {
+            Some(Repeat { min, max }) => ast::Node::Repeat {
+                node: Box::new(node),
+                min: *min as usize,
+                max: if *max == INFINITE {
+                    None
+                } else {
+                    Some(*max as usize)
+                },
+            },
+            None => node,
+        }
+    }
+}
+
+fn lower_all(nodes: &[Node]) -> Vec<ast::Node> {
+    nodes.iter().map(Node::lower).collect()
+}
+
+/// Deepest nesting of groups accepted. The parser and everything that
+/// walks its tree recurse once per level, so deeper patterns are refused
+/// rather than allowed to overflow the stack.
+const NEST_MAX: usize = 128;
+
 fn pre_parse(mut s: Vec<u8>) -> Vec<u8> {
-    const BEGIN: &[u8] = &[b'^'];
-    const END: &[u8] = &[b'$'];
+    const BEGIN: &[u8] = b"^";
+    const END: &[u8] = b"$";
     if let Some(&b) = s.first() {
         if b != b'^' {
             s.splice(0..0, BEGIN.iter().cloned());
@@ -34,126 +85,139 @@ fn pre_parse(mut s: Vec<u8>) -> Vec<u8> {
     }
     if let Some(&b) = s.last() {
         if b != b'$' {
-            s.splice(s.len() - 1..s.len() - 1, END.iter().cloned());
+            s.extend_from_slice(END);
         }
     }
-    dbg!(&s);
     s
 }
 
-fn process_int<'a, I>(iter: &mut Peekable<I>, num: &mut i32) -> u8
+/// Read the digits at the front of `iter` into `num`, leaving whatever
+/// follows them. False if there were none.
+fn process_int<'a, I>(iter: &mut Peekable<I>, num: &mut i32) -> bool
 where
-    I: Iterator<Item = &'a u8>,
+    I: ExactSizeIterator<Item = &'a u8>,
 {
     let mut number_str = Vec::with_capacity(6);
-    let mut ret_ch = 0;
-    while let Some(ch) = iter.next() {
-        dbg!(&ch);
-        match ch {
-            b'0'..=b'9' => number_str.push(*ch),
-            _ => {
-                ret_ch = *ch;
-                break;
-            }
+    while let Some(&&ch) = iter.peek() {
+        if !ch.is_ascii_digit() {
+            break;
         }
+        number_str.push(ch);
+        iter.next();
     }
     if !number_str.is_empty() {
+        // Too many digits saturates, which the bounds check then rejects
         *num = String::from_utf8_lossy(number_str.as_slice())
             .parse::<i32>()
-            .unwrap();
+            .unwrap_or(i32::MAX);
     }
-    ret_ch
+    !number_str.is_empty()
 }
 
-fn process_defined_repeat<'a, 'b, I>(iter: &mut Peekable<I>, min: &'b mut i32, max: &'b mut i32)
+/// The bounds of a `{n}`, `{n,}` or `{n,m}` quantifier whose `{` has been
+/// read, or `None` if it is malformed.
+fn process_defined_repeat<'a, I>(iter: &mut Peekable<I>) -> Option<(i32, i32)>
 where
-    I: Iterator<Item = &'a u8>,
+    I: ExactSizeIterator<Item = &'a u8>,
 {
-    if let Some(ch) = iter.peek() {
-        match ch {
-            b'0'..=b'9' => {
-                let ch = process_int(iter, min);
-                match ch {
-                    b'}' => *max = *min,
-                    b',' => {
-                        iter.next();
-                        let _ = process_int(iter, max);
-                    }
-                    _ => (),
-                }
-            }
-            b',' => {
-                iter.next();
-                let _ = process_int(iter, max);
-            }
-            _ => (),
+    let mut min = 0;
+    if !process_int(iter, &mut min) {
+        return None;
+    }
+    let mut max = min;
+    if iter.peek() == Some(&&b',') {
+        iter.next();
+        max = INFINITE;
+        process_int(iter, &mut max);
+    }
+    match iter.peek() {
+        Some(b'}') => {
+            iter.next();
+            Some((min, max))
         }
+        _ => None,
     }
 }
 
-fn process_repeat<'a, I>(iter: &mut Peekable<I>) -> Option<(i32, i32)>
+fn process_repeat<'a, I>(iter: &mut Peekable<I>) -> Result<Option<(i32, i32)>, Fault>
 where
-    I: Iterator<Item = &'a u8>,
+    I: ExactSizeIterator<Item = &'a u8>,
 {
-    let mut min = 0;
-    let mut max = 65536;
-    if let Some(ch) = iter.peek() {
-        match ch {
-            b'?' => {
-                iter.next();
-                Some((0, 1))
-            }
-            b'*' => {
-                iter.next();
-                Some((0, max))
-            }
-            b'+' => {
-                iter.next();
-                Some((1, max))
-            }
-            b'{' => {
-                iter.next();
-                process_defined_repeat(iter, &mut min, &mut max);
-                Some((min, max))
+    let start = iter.len();
+    let repeat = match iter.peek() {
+        Some(b'?') => {
+            iter.next();
+            Some((0, 1))
+        }
+        Some(b'*') => {
+            iter.next();
+            Some((0, INFINITE))
+        }
+        Some(b'+') => {
+            iter.next();
+            Some((1, INFINITE))
+        }
+        Some(b'{') => {
+            iter.next();
+            match process_defined_repeat(iter) {
+                Some(bounds) => Some(bounds),
+                None => return Err(Fault::new(ErrorKind::BadRepeat, start, iter.len())),
             }
-            _ => None,
         }
-    } else {
-        None
+        _ => None,
+    };
+    // Lazy and possessive modifiers don't change what can be matched
+    if repeat.is_some() {
+        if let Some(b'?') | Some(b'+') = iter.peek() {
+            iter.next();
+        }
     }
+    Ok(repeat)
 }
 
-fn process_select<'a, I>(iter: &mut Peekable<I>, ends: &mut Vec<u8>) -> Node
+fn process_select<'a, I>(
+    iter: &mut Peekable<I>,
+    ends: &mut Vec<u8>,
+    groups: &mut usize,
+    universe: &CharClass,
+) -> Result<Node, Fault>
 where
-    I: Iterator<Item = &'a u8>,
+    I: ExactSizeIterator<Item = &'a u8>,
 {
     ends.push(b'|');
-    let mut select = Vec::new();
-    while let Some(&ch) = iter.peek() {
-        select.push(process_seq(iter, ends));
+    let mut select = vec![process_seq(iter, ends, groups, universe)?];
+    while let Some(b'|') = iter.peek() {
+        iter.next();
+        select.push(process_seq(iter, ends, groups, universe)?);
     }
     ends.pop();
-    Node::Select(select, None)
+    if select.len() == 1 {
+        Ok(select.pop().unwrap())
+    } else {
+        Ok(Node::Select(select, None))
+    }
 }
 
 fn process_range<'a, I>(iter: &mut Peekable<I>, to: &mut u8)
 where
-    I: Iterator<Item = &'a u8>,
+    I: ExactSizeIterator<Item = &'a u8>,
 {
-    while let Some(ch) = iter.next() {
-        if *ch != b']' {
-            *to = *ch;
-            break;
-        }
+    if let Some(ch) = iter.next() {
+        *to = match ch {
+            b'\\' => match process_slash(iter, false) {
+                Node::Text(ch, _) => ch,
+                _ => *to,
+            },
+            _ => *ch,
+        };
     }
 }
 
 fn process_slash<'a, I>(iter: &mut Peekable<I>, back_ref: bool) -> Node
 where
-    I: Iterator<Item = &'a u8>,
+    I: ExactSizeIterator<Item = &'a u8>,
 {
     let ch = if let Some(slash) = iter.next() {
-        dbg!(&slash);
         match slash {
             b'n' => b'\n',
             b'r' => b'\r',
@@ -163,218 +227,243 @@ where
     } else {
         b'\\'
     };
-    dbg!(&ch);
     match ch {
-        b'd' => Node::Charset((b'0'..=b'9').collect(), true, None),
-        b'D' => Node::Charset((b'0'..=b'9').collect(), false, None),
-        b's' => Node::Charset(vec![b'\t', b' '], true, None),
-        b'S' => Node::Charset(vec![b'\t', b' '], false, None),
-        b'w' => {
-            let mut charset = Vec::with_capacity(255);
-            charset.extend_from_slice((b'A'..=b'Z').collect::<Vec<_>>().as_slice());
-            charset.extend_from_slice((b'a'..=b'z').collect::<Vec<_>>().as_slice());
-            charset.extend_from_slice((b'0'..=b'9').collect::<Vec<_>>().as_slice());
-            charset.push(b'_');
-            Node::Charset(charset, true, None)
-        }
-        b'W' => {
-            let mut charset = Vec::with_capacity(255);
-            charset.extend_from_slice((b'A'..=b'Z').collect::<Vec<_>>().as_slice());
-            charset.extend_from_slice((b'a'..=b'z').collect::<Vec<_>>().as_slice());
-            charset.extend_from_slice((b'0'..=b'9').collect::<Vec<_>>().as_slice());
-            charset.push(b'_');
-            Node::Charset(charset, false, None)
-        }
+        b'd' => Node::Charset(DIGIT, true, None),
+        b'D' => Node::Charset(DIGIT, false, None),
+        b's' => Node::Charset(SPACE, true, None),
+        b'S' => Node::Charset(SPACE, false, None),
+        b'w' => Node::Charset(WORD, true, None),
+        b'W' => Node::Charset(WORD, false, None),
         b'0'..=b'9' if back_ref => Node::Ref((ch - b'0') as usize),
         _ => Node::Text(ch, None),
     }
 }
 
-fn process_set<'a, I>(iter: &mut Peekable<I>, ends: &mut Vec<u8>) -> Node
+fn process_set<'a, I>(
+    iter: &mut Peekable<I>,
+    ends: &mut Vec<u8>,
+    universe: &CharClass,
+) -> Result<Node, Fault>
 where
-    I: Iterator<Item = &'a u8>,
+    I: ExactSizeIterator<Item = &'a u8>,
 {
-    let mut charset = Vec::with_capacity(255);
-    let mut begin = true;
-    let mut prev = 0;
-    let mut exclude = true;
+    // Just after the `[`
+    let open = iter.len() + 1;
+    let mut charset = CharClass::new();
+    // What `\D` and friends leave out, if the set has any
+    let mut except: Option<CharClass> = None;
+    let mut include = true;
+    let mut closed = false;
     ends.push(b']');
-    while let Some(&ch) = iter.peek() {
-        dbg!(&(*ch as char));
-        dbg!(prev as char);
-
-        match ch {
-            b'^' if begin => {
-                begin = false;
-                exclude = false;
-                iter.next();
-                continue;
-            }
-            b'-' if prev > 0 => {
-                iter.next();
-                let mut to = 0;
-                process_range(iter, &mut to);
-                charset.append(&mut (prev..=to).collect());
-                prev = 0;
-                continue;
-            }
-            b'|' => {
-                if prev > 0 {
-                    charset.push(prev);
-                }
-                iter.next();
-                continue;
-            }
-            _ => (),
-        }
-        if prev > 0 {
-            charset.push(prev);
-        }
-        match ch {
+    if let Some(b'^') = iter.peek() {
+        include = false;
+        iter.next();
+    }
+    while let Some(ch) = iter.next() {
+        let start = iter.len() + 1;
+        let from = match ch {
             b']' => {
-                ends.pop();
-                iter.next();
-                if prev > 0 {
-                    charset.push(prev);
-                }
+                closed = true;
                 break;
             }
-            b'\\' => {
-                iter.next();
-                let node = process_slash(iter, false);
-                match node {
-                    Node::Charset(mut v, _, _) => charset.append(&mut v),
-                    _ => (),
+            b'|' => continue,
+            b'\\' => match process_slash(iter, false) {
+                Node::Text(ch, _) => ch,
+                Node::Charset(class, true, _) => {
+                    charset = charset.union(class.intersection(*universe));
+                    continue;
+                }
+                Node::Charset(class, false, _) => {
+                    except = Some(except.map_or(class, |e| e.intersection(class)));
+                    continue;
                 }
+                _ => continue,
+            },
+            _ => *ch,
+        };
+        // Bytes named on their own have to be in the universe, while ranges
+        // just lose what isn't
+        let named = |ch: u8, to: usize| match universe.contains(ch) {
+            true => Ok(ch),
+            false => Err(Fault::new(ErrorKind::NotInUniverse, start, to)),
+        };
+        if let Some(b'-') = iter.peek() {
+            iter.next();
+            // A dash just before the closing bracket is a literal
+            if let Some(b']') | None = iter.peek() {
+                charset.insert(named(from, iter.len() + 1)?);
+                charset.insert(named(b'-', iter.len())?);
+            } else {
+                let mut to = from;
+                process_range(iter, &mut to);
+                if to < from {
+                    return Err(Fault::new(ErrorKind::BadRange, start, iter.len()));
+                }
+                charset = charset.union(CharClass::range(from, to).intersection(*universe));
             }
-            _ => (),
+        } else {
+            charset.insert(named(from, iter.len())?);
         }
-        prev = *ch;
-        iter.next();
     }
-    if !charset.is_empty() {
-        Node::Charset(charset, exclude, None)
-    } else {
-        Node::Text(b'[', None)
+    ends.pop();
+    if !closed {
+        return Err(Fault::new(ErrorKind::UnclosedSet, open, 0));
+    }
+    match except {
+        // The set is the whole alphabet but for what is in `except` and not
+        // named, so negating it swaps which of the two it is
+        Some(except) => Ok(Node::Charset(
+            except.intersection(charset.complement()),
+            !include,
+            None,
+        )),
+        None if !charset.is_empty() => Ok(Node::Charset(charset, include, None)),
+        None => Ok(Node::Text(b'[', None)),
     }
 }
 
-fn is_sub_expr<'a, I>(iter: &mut Peekable<I>) -> u8
+/// Read the `?..` prefix after an opening parenthesis, if any, numbering
+/// the group when it captures. Returns `None` for an unknown prefix.
+fn group_kind<'a, I>(iter: &mut Peekable<I>, groups: &mut usize) -> Option<GroupKind>
 where
-    I: Iterator<Item = &'a u8>,
+    I: ExactSizeIterator<Item = &'a u8>,
 {
-    let mut is_subexp = false;
-    let mut ch: u8 = 0;
-    while let Some(n) = iter.peek() {
-        match n {
-            b'?' => is_subexp = true,
-            b':' | b'=' | b'!' | b'>' if is_subexp => {
-                iter.next();
-                break;
+    if let Some(b'?') = iter.peek() {
+        iter.next();
+        let kind = match iter.next() {
+            Some(b':') => GroupKind::NonCapture,
+            Some(b'=') => GroupKind::Lookahead { negative: false },
+            Some(b'!') => GroupKind::Lookahead { negative: true },
+            Some(b'>') => GroupKind::Atomic,
+            Some(b'<') if matches!(iter.peek(), Some(b'=') | Some(b'!')) => {
+                let negative = iter.next() == Some(&b'!');
+                GroupKind::Lookbehind { negative }
             }
-            _ => {
-                ch = **n;
-                break;
+            // A named group, which captures like any other
+            Some(b'<') => {
+                iter.by_ref().take_while(|&&ch| ch != b'>').for_each(drop);
+                return Some(next_group(groups));
             }
-        }
-        iter.next();
+            Some(b'P') if iter.next() == Some(&b'<') => {
+                iter.by_ref().take_while(|&&ch| ch != b'>').for_each(drop);
+                return Some(next_group(groups));
+            }
+            _ => return None,
+        };
+        return Some(kind);
     }
-    ch
+    Some(next_group(groups))
 }
 
-fn process_group<'a, I>(iter: &mut Peekable<I>, ends: &mut Vec<u8>) -> Node
+fn next_group(groups: &mut usize) -> GroupKind {
+    *groups += 1;
+    GroupKind::Capture(*groups)
+}
+
+fn process_group<'a, I>(
+    iter: &mut Peekable<I>,
+    ends: &mut Vec<u8>,
+    groups: &mut usize,
+    universe: &CharClass,
+) -> Result<Node, Fault>
 where
-    I: Iterator<Item = &'a u8>,
+    I: ExactSizeIterator<Item = &'a u8>,
 {
-    let mut group = Vec::new();
+    // Just after the `(`
+    let open = iter.len() + 1;
+    if ends.iter().filter(|&&e| e == b')').count() >= NEST_MAX {
+        return Err(Fault::new(ErrorKind::TooDeep, open, open - 1));
+    }
     ends.push(b')');
-    let mark = is_sub_expr(iter);
-    group.push(process_seq(iter, ends));
+    let kind = group_kind(iter, groups).ok_or(Fault::new(ErrorKind::BadGroup, open, iter.len()))?;
+    let group = vec![process_select(iter, ends, groups, universe)?];
     ends.pop();
-    if !group.is_empty() {
-        return Node::Group(group, mark as usize, None);
+    match iter.next() {
+        Some(b')') => Ok(Node::Group(group, kind, None)),
+        _ => Err(Fault::new(ErrorKind::UnbalancedParen, open, open - 1)),
     }
-    return Node::Text(b'(', None);
 }
 
-fn process_seq<'a, I>(iter: &mut Peekable<I>, ends: &mut Vec<u8>) -> Node
+fn process_seq<'a, I>(
+    iter: &mut Peekable<I>,
+    ends: &mut Vec<u8>,
+    groups: &mut usize,
+    universe: &CharClass,
+) -> Result<Node, Fault>
 where
-    I: Iterator<Item = &'a u8>,
+    I: ExactSizeIterator<Item = &'a u8>,
 {
     let mut seq = Vec::new();
-    let mut node: Option<Node> = None;
-    let mut begin = true;
     while let Some(&ch) = iter.peek() {
-        dbg!(*ch as char);
-        if begin {
-            if *ch == b'^' {
-                node = Some(Node::Edge(true));
-            } else {
-                begin = false;
-            }
-        }
-
+        // Leave the terminator for the enclosing select or group
         if ends.iter().any(|e| e == ch) {
-            iter.next();
             break;
         }
-        node = match ch {
-            b'|' => {
-                iter.next();
-                Some(process_select(iter, ends))
-            }
-            b'$' => {
-                iter.next();
-                Some(Node::Edge(false))
-            }
-            b'.' => {
-                iter.next();
-                Some(Node::Charset(vec![b'\n'], true, None))
-            }
-            b'[' => {
-                iter.next();
-                Some(process_set(iter, ends))
-            }
-            b'(' => {
-                iter.next();
-                Some(process_group(iter, ends))
-            }
-            b'\\' => {
-                iter.next();
-                Some(process_slash(iter, true))
-            }
-            _ => {
-                iter.next();
-                Some(Node::Text(*ch, None))
+        let start = iter.len();
+        iter.next();
+        let mut node = match ch {
+            b'^' => Node::Edge(true),
+            b'$' => Node::Edge(false),
+            b'.' => Node::Any(None),
+            b'[' => process_set(iter, ends, universe)?,
+            b'(' => process_group(iter, ends, groups, universe)?,
+            b'\\' => process_slash(iter, true),
+            b')' => return Err(Fault::new(ErrorKind::UnbalancedParen, start, iter.len())),
+            b'*' | b'+' | b'?' => {
+                return Err(Fault::new(ErrorKind::DanglingQuantifier, start, iter.len()))
             }
+            _ => Node::Text(*ch, None),
         };
-        if let Some(ref mut n) = node {
-            if let Some((min, max)) = process_repeat(iter) {
-                match n {
-                    Node::Text(_, r)
-                    | Node::Charset(_, _, r)
-                    | Node::Seq(_, r)
-                    | Node::Group(_, _, r)
-                    | Node::Select(_, r) => *r = Some(Repeat { min, max }),
-                    _ => (),
-                }
+        if let Node::Text(ch, _) = node {
+            if !universe.contains(ch) {
+                return Err(Fault::new(ErrorKind::NotInUniverse, start, iter.len()));
             }
         }
-        if let Some(n) = node {
-            seq.push(n);
+        let quantifier = iter.len();
+        if let Some((min, max)) = process_repeat(iter)? {
+            let fault = |kind| Err(Fault::new(kind, quantifier, iter.len()));
+            if min > max || max > REPEAT_MAX && max != INFINITE {
+                return fault(ErrorKind::BadRepeat);
+            }
+            match node {
+                Node::Any(ref mut r)
+                | Node::Text(_, ref mut r)
+                | Node::Charset(_, _, ref mut r)
+                | Node::Seq(_, ref mut r)
+                | Node::Group(_, _, ref mut r)
+                | Node::Select(_, ref mut r) => *r = Some(Repeat { min, max }),
+                Node::Ref(n) => node = Node::Seq(vec![Node::Ref(n)], Some(Repeat { min, max })),
+                Node::Edge(_) => return fault(ErrorKind::DanglingQuantifier),
+            }
         }
-        dbg!(*ch as char);
         // Add the node to the parent sequence
+        seq.push(node);
     }
-    Node::Seq(seq, None)
+    Ok(Node::Seq(seq, None))
+}
+
+pub fn parse(re: Vec<u8>) -> Result<Node, ParseError> {
+    parse_in(re, &CharClass::all())
 }
 
-pub fn parse<'a>(re: Vec<u8>) -> Result<(), ParseError> {
-    let re = pre_parse(re);
-    let iter = re.iter();
+/// Parse a pattern that may only name bytes out of `universe`. Ranges and
+/// classes in sets are cut down to it instead.
+pub fn parse_in(re: Vec<u8>, universe: &CharClass) -> Result<Node, ParseError> {
+    // The anchors `pre_parse` adds would hide a trailing backslash
+    let slashes = re.iter().rev().take_while(|&&ch| ch == b'\\').count();
+    if slashes % 2 == 1 {
+        let end = re.len();
+        return Err(ParseError::new(ErrorKind::BadEscape, end - 1..end, re));
+    }
+    let shift = match re.first() {
+        Some(b'^') | None => 0,
+        Some(_) => 1,
+    };
+    let full = pre_parse(re.clone());
+    let mut iter = full.iter().peekable();
     let mut ends = Vec::with_capacity(16);
-    let parent = process_seq(&mut iter.peekable(), &mut ends);
-    Ok(())
+    process_select(&mut iter, &mut ends, &mut 0, universe)
+        .map_err(|fault| fault.locate(full.len(), shift, re))
 }
 
 #[cfg(test)]
@@ -383,7 +472,7 @@ mod tests {
     #[test]
     fn integer() {
         let mut num = 0;
-        super::process_int(&mut &mut "1234".as_bytes().iter().peekable(), &mut num);
+        super::process_int(&mut "1234".as_bytes().iter().peekable(), &mut num);
         assert_eq!(num, 1234);
     }
 
@@ -391,36 +480,44 @@ mod tests {
     fn range() {
         assert_eq!(
             super::process_repeat(&mut "?".as_bytes().iter().peekable()),
-            Some((0, 1))
+            Ok(Some((0, 1)))
         );
         assert_eq!(
             super::process_repeat(&mut "*".as_bytes().iter().peekable()),
-            Some((0, 65536))
+            Ok(Some((0, 65536)))
         );
         assert_eq!(
             super::process_repeat(&mut "+".as_bytes().iter().peekable()),
-            Some((1, 65536))
+            Ok(Some((1, 65536)))
         );
     }
     #[test]
     fn range_complex_a() {
         assert_eq!(
             super::process_repeat(&mut "{8}".as_bytes().iter().peekable()),
-            Some((8, 8))
+            Ok(Some((8, 8)))
         );
     }
     #[test]
     fn range_complex_b() {
         assert_eq!(
             super::process_repeat(&mut "{8,}".as_bytes().iter().peekable()),
-            Some((8, 65536))
+            Ok(Some((8, 65536)))
         );
     }
     #[test]
     fn range_complex_c() {
+        // A minimum is required, as in ECMAScript
+        assert!(super::process_repeat(&mut "{,9}".as_bytes().iter().peekable()).is_err());
+    }
+    #[test]
+    fn range_complex_d() {
+        let mut iter = "{2,6}x".as_bytes().iter().peekable();
+        assert_eq!(super::process_repeat(&mut iter), Ok(Some((2, 6))));
+        assert_eq!(iter.next(), Some(&b'x'));
         assert_eq!(
-            super::process_repeat(&mut "{,9}".as_bytes().iter().peekable()),
-            Some((0, 9))
+            super::process_repeat(&mut "+?".as_bytes().iter().peekable()),
+            Ok(Some((1, 65536)))
         );
     }
     #[test]
@@ -449,38 +546,33 @@ mod tests {
     fn slash_test_c() {
         assert_eq!(
             super::process_slash(&mut r"d".as_bytes().iter().peekable(), false),
-            Node::Charset((b'0'..=b'9').collect(), true, None)
+            Node::Charset(DIGIT, true, None)
         );
         assert_eq!(
             super::process_slash(&mut r"D".as_bytes().iter().peekable(), false),
-            Node::Charset((b'0'..=b'9').collect(), false, None)
+            Node::Charset(DIGIT, false, None)
         );
     }
     #[test]
     fn slash_test_d() {
         assert_eq!(
             super::process_slash(&mut r"s".as_bytes().iter().peekable(), false),
-            Node::Charset(vec![b'\t', b' '], true, None)
+            Node::Charset(SPACE, true, None)
         );
         assert_eq!(
             super::process_slash(&mut r"S".as_bytes().iter().peekable(), false),
-            Node::Charset(vec![b'\t', b' '], false, None)
+            Node::Charset(SPACE, false, None)
         );
     }
     #[test]
     fn slash_test_e() {
-        let mut charset = Vec::with_capacity(255);
-        charset.extend_from_slice((b'A'..=b'Z').collect::<Vec<_>>().as_slice());
-        charset.extend_from_slice((b'a'..=b'z').collect::<Vec<_>>().as_slice());
-        charset.extend_from_slice((b'0'..=b'9').collect::<Vec<_>>().as_slice());
-        charset.push(b'_');
         assert_eq!(
             super::process_slash(&mut r"w".as_bytes().iter().peekable(), false),
-            Node::Charset(charset.clone(), true, None)
+            Node::Charset(WORD, true, None)
         );
         assert_eq!(
             super::process_slash(&mut r"W".as_bytes().iter().peekable(), false),
-            Node::Charset(charset, false, None)
+            Node::Charset(WORD, false, None)
         );
     }
     #[test]
@@ -504,138 +596,358 @@ mod tests {
     #[test]
     fn set_test_a() {
         assert_eq!(
-            super::process_set(&mut r"a-z".as_bytes().iter().peekable(), &mut Vec::new()),
-            Node::Charset((b'a'..=b'z').collect::<Vec<_>>(), true, None)
+            super::process_set(
+                &mut r"a-z]".as_bytes().iter().peekable(),
+                &mut Vec::new(),
+                &CharClass::all()
+            ),
+            Ok(Node::Charset(CharClass::range(b'a', b'z'), true, None))
         );
         assert_eq!(
-            super::process_set(&mut r"A-Z".as_bytes().iter().peekable(), &mut Vec::new()),
-            Node::Charset((b'A'..=b'Z').collect::<Vec<_>>(), true, None)
+            super::process_set(
+                &mut r"A-Z]".as_bytes().iter().peekable(),
+                &mut Vec::new(),
+                &CharClass::all()
+            ),
+            Ok(Node::Charset(CharClass::range(b'A', b'Z'), true, None))
         );
         assert_eq!(
-            super::process_set(&mut r"0-9".as_bytes().iter().peekable(), &mut Vec::new()),
-            Node::Charset((b'0'..=b'9').collect::<Vec<_>>(), true, None)
+            super::process_set(
+                &mut r"0-9]".as_bytes().iter().peekable(),
+                &mut Vec::new(),
+                &CharClass::all()
+            ),
+            Ok(Node::Charset(CharClass::range(b'0', b'9'), true, None))
         );
     }
     #[test]
     fn set_test_b() {
         assert_eq!(
-            super::process_set(&mut r"e-l".as_bytes().iter().peekable(), &mut Vec::new()),
-            Node::Charset((b'e'..=b'l').collect::<Vec<_>>(), true, None)
+            super::process_set(
+                &mut r"e-l]".as_bytes().iter().peekable(),
+                &mut Vec::new(),
+                &CharClass::all()
+            ),
+            Ok(Node::Charset(CharClass::range(b'e', b'l'), true, None))
         );
     }
     #[test]
     fn set_test_c() {
         assert_eq!(
-            super::process_set(&mut r"^e-l".as_bytes().iter().peekable(), &mut Vec::new()),
-            Node::Charset((b'e'..=b'l').collect::<Vec<_>>(), false, None)
+            super::process_set(
+                &mut r"^e-l]".as_bytes().iter().peekable(),
+                &mut Vec::new(),
+                &CharClass::all()
+            ),
+            Ok(Node::Charset(CharClass::range(b'e', b'l'), false, None))
         );
         assert_eq!(
-            super::process_set(&mut r"^0-9".as_bytes().iter().peekable(), &mut Vec::new()),
-            Node::Charset((b'0'..=b'9').collect::<Vec<_>>(), false, None)
+            super::process_set(
+                &mut r"^0-9]".as_bytes().iter().peekable(),
+                &mut Vec::new(),
+                &CharClass::all()
+            ),
+            Ok(Node::Charset(CharClass::range(b'0', b'9'), false, None))
         );
     }
     #[test]
     fn set_test_d() {
         assert_eq!(
-            super::process_set(&mut r"hello|".as_bytes().iter().peekable(), &mut Vec::new()),
-            Node::Charset("hello".as_bytes().to_vec(), true, None)
+            super::process_set(
+                &mut r"hello|]".as_bytes().iter().peekable(),
+                &mut Vec::new(),
+                &CharClass::all()
+            ),
+            Ok(Node::Charset(CharClass::from_bytes(b"hello"), true, None))
+        );
+    }
+
+    #[test]
+    fn set_test_e() {
+        assert_eq!(
+            super::process_set(
+                &mut r"\da-c\.-]".as_bytes().iter().peekable(),
+                &mut Vec::new(),
+                &CharClass::all()
+            ),
+            Ok(Node::Charset(
+                CharClass::from_bytes(b"0123456789abc.-"),
+                true,
+                None
+            ))
+        );
+        assert_eq!(
+            super::process_set(
+                &mut r"ab]c".as_bytes().iter().peekable(),
+                &mut Vec::new(),
+                &CharClass::all()
+            ),
+            Ok(Node::Charset(CharClass::from_bytes(b"ab"), true, None))
+        );
+    }
+
+    #[test]
+    fn set_test_f() {
+        let set = |re: &str| {
+            super::process_set(
+                &mut re.as_bytes().iter().peekable(),
+                &mut Vec::new(),
+                &CharClass::all(),
+            )
+        };
+        assert_eq!(set(r"\D]"), Ok(Node::Charset(DIGIT, false, None)));
+        assert_eq!(
+            set(r"\s\S]"),
+            Ok(Node::Charset(CharClass::new(), false, None))
+        );
+        assert_eq!(
+            set(r"^\D\W1]"),
+            Ok(Node::Charset(
+                CharClass::from_bytes(b"023456789"),
+                true,
+                None
+            ))
+        );
+    }
+
+    #[test]
+    fn select_a() {
+        assert_eq!(
+            super::parse(b"a|b".to_vec()).unwrap(),
+            Node::Select(
+                vec![
+                    Node::Seq(vec![Node::Edge(true), Node::Text(b'a', None)], None),
+                    Node::Seq(vec![Node::Text(b'b', None), Node::Edge(false)], None)
+                ],
+                None
+            )
         );
     }
 
     #[test]
     fn sub_expr_a() {
+        let kind = |re: &str| super::group_kind(&mut re.as_bytes().iter().peekable(), &mut 0);
+        assert_eq!(kind("?:"), Some(GroupKind::NonCapture));
+        assert_eq!(kind("a"), Some(GroupKind::Capture(1)));
+        assert_eq!(kind(""), Some(GroupKind::Capture(1)));
+        assert_eq!(kind("?!x"), Some(GroupKind::Lookahead { negative: true }));
         assert_eq!(
-            super::is_sub_expr(&mut r"?:".as_bytes().iter().peekable()),
-            0
+            kind("?<=x"),
+            Some(GroupKind::Lookbehind { negative: false })
         );
+        assert_eq!(kind("?>x"), Some(GroupKind::Atomic));
+        assert_eq!(kind("?i)"), None);
+        let mut iter = "?<name>x".as_bytes().iter().peekable();
         assert_eq!(
-            super::is_sub_expr(&mut r"?a".as_bytes().iter().peekable()),
-            97
+            super::group_kind(&mut iter, &mut 4),
+            Some(GroupKind::Capture(5))
         );
-        assert_eq!(super::is_sub_expr(&mut r"".as_bytes().iter().peekable()), 0);
+        assert_eq!(iter.next(), Some(&b'x'));
     }
 
     #[test]
     fn group_a() {
-        let mut charset = Vec::with_capacity(255);
-        charset.extend_from_slice((b'a'..=b'z').collect::<Vec<_>>().as_slice());
-        charset.extend_from_slice((b'0'..=b'9').collect::<Vec<_>>().as_slice());
+        let charset = CharClass::range(b'a', b'z').union(DIGIT);
         assert_eq!(
             super::process_group(
                 &mut r"[a-z|0-9])".as_bytes().iter().peekable(),
-                &mut Vec::new()
+                &mut Vec::new(),
+                &mut 0,
+                &CharClass::all()
             ),
-            Node::Group(
+            Ok(Node::Group(
                 vec![Node::Seq(vec![Node::Charset(charset, true, None)], None)],
-                91,
+                GroupKind::Capture(1),
                 None
-            )
+            ))
         );
     }
 
     #[test]
     fn group_b() {
-        let mut charset = Vec::with_capacity(255);
-        charset.extend_from_slice((b'a'..=b'z').collect::<Vec<_>>().as_slice());
-        charset.extend_from_slice((b'0'..=b'9').collect::<Vec<_>>().as_slice());
+        let charset = CharClass::range(b'a', b'z').union(DIGIT);
         assert_eq!(
             super::process_group(
                 &mut r"?:[a-z|0-9])".as_bytes().iter().peekable(),
-                &mut Vec::new()
+                &mut Vec::new(),
+                &mut 0,
+                &CharClass::all()
             ),
-            Node::Group(
+            Ok(Node::Group(
                 vec![Node::Seq(vec![Node::Charset(charset, true, None)], None)],
-                0,
+                GroupKind::NonCapture,
                 None
-            )
+            ))
         );
     }
 
     #[test]
     fn group_c() {
-        let mut charset = Vec::with_capacity(255);
-        charset.extend_from_slice((b'a'..=b'z').collect::<Vec<_>>().as_slice());
-        charset.extend_from_slice((b'0'..=b'9').collect::<Vec<_>>().as_slice());
         assert_eq!(
             super::process_group(
                 &mut r"?:https|ftp)://".as_bytes().iter().peekable(),
-                &mut Vec::new()
+                &mut Vec::new(),
+                &mut 0,
+                &CharClass::all()
             ),
-            Node::Group(
-                vec![Node::Seq(
+            Ok(Node::Group(
+                vec![Node::Select(
                     vec![
-                        Node::Text(104, None),
-                        Node::Text(116, None),
-                        Node::Text(116, None),
-                        Node::Text(112, None),
-                        Node::Text(115, None),
-                        Node::Select(
+                        Node::Seq(
+                            vec![
+                                Node::Text(104, None),
+                                Node::Text(116, None),
+                                Node::Text(116, None),
+                                Node::Text(112, None),
+                                Node::Text(115, None)
+                            ],
+                            None
+                        ),
+                        Node::Seq(
                             vec![
-                                Node::Seq(
-                                    vec![
-                                        Node::Text(102, None),
-                                        Node::Text(116, None),
-                                        Node::Text(112, None)
-                                    ],
-                                    None
-                                ),
-                                Node::Seq(
-                                    vec![
-                                        Node::Text(58, None),
-                                        Node::Text(47, None),
-                                        Node::Text(47, None)
-                                    ],
-                                    None
-                                )
+                                Node::Text(102, None),
+                                Node::Text(116, None),
+                                Node::Text(112, None)
                             ],
                             None
                         )
                     ],
                     None
                 )],
-                0,
+                GroupKind::NonCapture,
                 None
-            )
+            ))
+        );
+    }
+
+    #[test]
+    fn group_d() {
+        fn kinds(node: &Node, out: &mut Vec<GroupKind>) {
+            match node {
+                Node::Group(nodes, kind, _) => {
+                    out.push(*kind);
+                    nodes.iter().for_each(|n| kinds(n, out));
+                }
+                Node::Seq(nodes, _) | Node::Select(nodes, _) => {
+                    nodes.iter().for_each(|n| kinds(n, out))
+                }
+                _ => (),
+            }
+        }
+        let mut out = Vec::new();
+        kinds(
+            &parse(b"(a(?:b)(c)(?=d))|(?<n>e)(?>f)(g)".to_vec()).unwrap(),
+            &mut out,
+        );
+        assert_eq!(
+            out,
+            vec![
+                GroupKind::Capture(1),
+                GroupKind::NonCapture,
+                GroupKind::Capture(2),
+                GroupKind::Lookahead { negative: false },
+                GroupKind::Capture(3),
+                GroupKind::Atomic,
+                GroupKind::Capture(4),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_a() {
+        let err = |re: &str| {
+            let e = super::parse(re.as_bytes().to_vec()).unwrap_err();
+            (e.kind, e.span)
+        };
+        assert_eq!(err("ab(cd"), (ErrorKind::UnbalancedParen, 2..3));
+        assert_eq!(err("ab)c"), (ErrorKind::UnbalancedParen, 2..3));
+        assert_eq!(err("x[z-a]"), (ErrorKind::BadRange, 2..5));
+        assert_eq!(err("[abc"), (ErrorKind::UnclosedSet, 0..4));
+        assert_eq!(err("*a"), (ErrorKind::DanglingQuantifier, 0..1));
+        assert_eq!(err("a|?"), (ErrorKind::DanglingQuantifier, 2..3));
+        assert_eq!(err("a**"), (ErrorKind::DanglingQuantifier, 2..3));
+        assert_eq!(err("^*"), (ErrorKind::DanglingQuantifier, 1..2));
+    }
+
+    #[test]
+    fn error_b() {
+        let err = |re: &str| {
+            let e = super::parse(re.as_bytes().to_vec()).unwrap_err();
+            (e.kind, e.span)
+        };
+        assert_eq!(err("a{3,2}"), (ErrorKind::BadRepeat, 1..6));
+        assert_eq!(err("a{99999999999}"), (ErrorKind::BadRepeat, 1..14));
+        assert_eq!(err("x{abc}"), (ErrorKind::BadRepeat, 1..2));
+        assert_eq!(err("a{3"), (ErrorKind::BadRepeat, 1..3));
+        assert_eq!(err("a{2,x}b"), (ErrorKind::BadRepeat, 1..4));
+        assert_eq!(err("a{,}"), (ErrorKind::BadRepeat, 1..2));
+        assert_eq!(err(r"ab\"), (ErrorKind::BadEscape, 2..3));
+        assert_eq!(err("(?i)a"), (ErrorKind::BadGroup, 0..3));
+        assert!(super::parse(br"a\\".to_vec()).is_ok());
+        assert!(super::parse(b"a*?b{2,}+(?:c)".to_vec()).is_ok());
+    }
+
+    #[test]
+    fn error_c() {
+        let deep = |n| format!("{}a{}", "(".repeat(n), ")".repeat(n)).into_bytes();
+        assert!(super::parse(deep(NEST_MAX)).is_ok());
+        let e = super::parse(deep(100_000)).unwrap_err();
+        assert_eq!(
+            (e.kind, e.span),
+            (ErrorKind::TooDeep, NEST_MAX..NEST_MAX + 1)
+        );
+        for re in [
+            "[", "(", "(?", "(?P", "(?<a", "{", "a{", "a{,", "[a-", "\\\\\\",
+        ] {
+            let _ = super::parse(re.as_bytes().to_vec());
+        }
+    }
+
+    #[test]
+    fn error_d() {
+        let lower = CharClass::range(b'a', b'z');
+        let err = |re: &str| {
+            let e = super::parse_in(re.as_bytes().to_vec(), &lower).unwrap_err();
+            (e.kind, e.span.clone(), e.caret())
+        };
+        assert_eq!(
+            err("abC"),
+            (ErrorKind::NotInUniverse, 2..3, "abC\n  ^".to_string())
+        );
+        assert_eq!(err(r"a\n").0, ErrorKind::NotInUniverse);
+        assert_eq!(err(r"a\n").1, 1..3);
+        assert_eq!(err("x[ab7]").1, 4..5);
+        assert_eq!(err("[a-]").1, 1..3);
+        assert_eq!(
+            super::parse_in(br"[\w.-z]\d".to_vec(), &lower),
+            super::parse(br"[a-z]\d".to_vec())
+        );
+    }
+
+    #[test]
+    fn lower_a() {
+        let text = |s: &[u8]| ast::Node::Text(s.to_vec());
+        assert_eq!(
+            super::parse(br"(a|[^b]){2,}?\1".to_vec()).unwrap().lower(),
+            ast::Node::Seq(vec![
+                ast::Node::Start,
+                ast::Node::Repeat {
+                    node: Box::new(ast::Node::Group(
+                        Box::new(ast::Node::Select(vec![
+                            ast::Node::Seq(vec![text(b"a")]),
+                            ast::Node::Seq(vec![ast::Node::Charset {
+                                class: CharClass::from_bytes(b"b"),
+                                negated: true
+                            }]),
+                        ])),
+                        GroupKind::Capture(1)
+                    )),
+                    min: 2,
+                    max: None
+                },
+                ast::Node::Ref(1),
+                ast::Node::End,
+            ])
         );
     }
 }
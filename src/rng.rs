@@ -0,0 +1,114 @@
+use crate::bigint::BigUint;
+
+/// Seedable xoshiro256** generator. The state is expanded from the seed
+/// with SplitMix64, so every seed, including zero, gives a usable stream
+/// and the same seed always reproduces the same numbers.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    s: [u64; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut x = seed;
+        let mut split_mix = || {
+            x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        Self {
+            s: [split_mix(), split_mix(), split_mix(), split_mix()],
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.s[1] << 17;
+        self.s[2] ^= self.s[0];
+        self.s[3] ^= self.s[1];
+        self.s[1] ^= self.s[2];
+        self.s[0] ^= self.s[3];
+        self.s[2] ^= t;
+        self.s[3] = self.s[3].rotate_left(45);
+        result
+    }
+
+    /// Uniform in `0..n` without modulo bias. `n` must not be zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+
+    /// Uniform in `0..n` for an arbitrarily large `n`, which must not be
+    /// zero.
+    pub fn below_big(&mut self, n: &BigUint) -> BigUint {
+        let bits = n.bits();
+        loop {
+            let mut digits = (0..bits.div_ceil(32))
+                .map(|_| self.next_u64() as u32)
+                .collect::<Vec<_>>();
+            if let Some(top) = digits.last_mut() {
+                *top >>= (32 - bits % 32) % 32;
+            }
+            let x = BigUint::from_digits(digits);
+            if x < *n {
+                return x;
+            }
+        }
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rng_a() {
+        let a = (0..8).map(|_| Rng::new(7).next_u64()).collect::<Vec<_>>();
+        assert!(a.iter().all(|&x| x == a[0]));
+        let mut r = Rng::new(7);
+        let b = (0..8).map(|_| r.next_u64()).collect::<Vec<_>>();
+        assert_eq!(b[0], a[0]);
+        assert_ne!(b[0], b[1]);
+        assert_ne!(Rng::new(0).next_u64(), 0);
+    }
+
+    #[test]
+    fn rng_b() {
+        let mut r = Rng::new(42);
+        let mut seen = [0; 6];
+        for _ in 0..6000 {
+            seen[r.below(6) as usize] += 1;
+        }
+        assert!(seen.iter().all(|&n| n > 800 && n < 1200));
+        assert!((0..1000)
+            .map(|_| r.next_f64())
+            .all(|f| (0.0..1.0).contains(&f)));
+    }
+
+    #[test]
+    fn rng_c() {
+        let mut r = Rng::new(5);
+        let n = BigUint::from(5);
+        let mut seen = [0; 5];
+        for _ in 0..5000 {
+            let x = r.below_big(&n);
+            seen[(0..5).position(|i| BigUint::from(i) == x).unwrap()] += 1;
+        }
+        assert!(seen.iter().all(|&n| n > 800 && n < 1200));
+        let big = &BigUint::from(u64::MAX) * &BigUint::from(u64::MAX);
+        assert!((0..100).all(|_| r.below_big(&big) < big));
+    }
+}
@@ -0,0 +1,509 @@
+use crate::bigint::BigUint;
+use crate::count::Paths;
+use crate::error::RenderError;
+use crate::expr::{Captures, Expr};
+use crate::matcher;
+use crate::regexgen::INFINITE;
+use crate::rng::Rng;
+use crate::uniform::{choose, Weights};
+use std::collections::HashMap;
+
+/// Values required of some capture groups, with where the groups inside
+/// them match within each value.
+type Fixed<'v> = HashMap<usize, (&'v [u8], Captures)>;
+
+/// How many iterations beyond its minimum an unbounded repeat (`*`, `+`,
+/// `{n,}`) takes when sampled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unbounded {
+    /// Uniformly anywhere from none up to the given number.
+    Uniform(usize),
+    /// Geometrically distributed with the given mean, so each extra
+    /// iteration is less likely than the one before.
+    Geometric(f64),
+}
+
+impl Default for Unbounded {
+    fn default() -> Self {
+        Unbounded::Uniform(8)
+    }
+}
+
+/// Which strings a uniform sampler draws from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Length {
+    /// Only strings of exactly this many bytes.
+    Exact(usize),
+    /// Strings of any length up to and including this many bytes.
+    UpTo(usize),
+}
+
+/// How a uniform sampler draws strings: by the structure of the pattern
+/// when each string has one way of being produced, or otherwise by its
+/// automaton, with captures found by matching the string afterwards.
+enum Draw<'a> {
+    Weights(Weights<'a>),
+    Paths(Paths, Vec<BigUint>),
+}
+
+/// Draws random strings matching a pattern.
+///
+/// By default every choice is a fair pick among the options that can still
+/// produce a string: branches of an alternation, bytes of a class and
+/// repeat counts between the bounds. This favours whatever is reached by
+/// fewer choices, so `(a|[a-z]{2})` gives `a` half of the time. In uniform
+/// mode choices are instead weighted by how many strings they lead to, so
+/// every string within the length limit is equally likely.
+///
+/// Two samplers built with the same seed and settings produce identical
+/// output.
+pub struct Sampler<'a> {
+    expr: &'a Expr,
+    rng: Rng,
+    unbounded: Unbounded,
+    uniform: Option<(Draw<'a>, Length)>,
+}
+
+impl<'a> Sampler<'a> {
+    pub fn new(expr: &'a Expr, seed: u64) -> Self {
+        Self {
+            expr,
+            rng: Rng::new(seed),
+            unbounded: Unbounded::default(),
+            uniform: None,
+        }
+    }
+
+    pub fn unbounded(mut self, unbounded: Unbounded) -> Self {
+        self.unbounded = unbounded;
+        self
+    }
+
+    /// Draw uniformly from the strings allowed by `length`. Repeats are
+    /// limited only by the length, so the unbounded repeat setting no
+    /// longer applies.
+    ///
+    /// Patterns that produce some string more than one way, like
+    /// `(\d|\d\d|[1-9]\d)`, still give each string once. Patterns with
+    /// backreferences are refused.
+    pub fn uniform(mut self, length: Length) -> Result<Self, RenderError> {
+        let max_len = match length {
+            Length::Exact(n) | Length::UpTo(n) => n,
+        };
+        if self.expr.has_refs() {
+            return Err(RenderError::Backreference);
+        }
+        let weights = Weights::new(self.expr, max_len);
+        let paths = Paths::new(self.expr, max_len);
+        let distinct = paths.counts();
+        let draw = match weights.counts() == distinct.as_slice() {
+            true => Draw::Weights(weights),
+            false => Draw::Paths(paths, distinct),
+        };
+        self.uniform = Some((draw, length));
+        Ok(self)
+    }
+
+    /// One random string, or `None` if the pattern matches nothing (within
+    /// the length limit, in uniform mode).
+    pub fn sample(&mut self) -> Option<Vec<u8>> {
+        self.sample_with_captures().map(|(s, _)| s)
+    }
+
+    /// One random string together with what each capture group matched in
+    /// it.
+    pub fn sample_with_captures(&mut self) -> Option<(Vec<u8>, Captures)> {
+        let mut out = Vec::new();
+        let mut groups = Captures::new(self.expr.groups());
+        if let Some((draw, length)) = &self.uniform {
+            let counts = match draw {
+                Draw::Weights(weights) => weights.counts(),
+                Draw::Paths(_, counts) => counts.as_slice(),
+            };
+            let len = match length {
+                Length::Exact(n) if counts[*n].is_zero() => return None,
+                Length::Exact(n) => *n,
+                Length::UpTo(_) if counts.iter().all(BigUint::is_zero) => return None,
+                Length::UpTo(_) => choose(&mut self.rng, counts.iter().cloned()),
+            };
+            match draw {
+                Draw::Weights(weights) => weights.sample(len, &mut self.rng, &mut out, &mut groups),
+                Draw::Paths(paths, _) => {
+                    paths.sample(len, &mut self.rng, &mut out);
+                    // Past what the matcher takes on, only the whole
+                    // string is known
+                    if let Ok(Some(found)) = matcher::captures(self.expr, &out, &HashMap::new()) {
+                        groups = found;
+                    }
+                }
+            }
+        } else if self.expr.is_void() {
+            return None;
+        } else {
+            self.walk(self.expr, &mut out, &mut groups, &Fixed::new())
+                .ok()?;
+        }
+        groups.set(0, Some(0..out.len()));
+        Some((out, groups))
+    }
+
+    /// A random string in which the numbered capture groups match exactly
+    /// the given values, with everything else drawn as by `sample`. Uniform
+    /// mode doesn't apply here.
+    ///
+    /// Every value has to be one its group can produce, including values
+    /// given for groups nested inside it.
+    pub fn render(
+        &mut self,
+        values: &HashMap<usize, Vec<u8>>,
+    ) -> Result<(Vec<u8>, Captures), RenderError> {
+        let mut fixed = Fixed::new();
+        let mut numbers = values.keys().collect::<Vec<_>>();
+        numbers.sort_unstable();
+        for &n in numbers {
+            let value = &values[&n];
+            let body = self.expr.group(n).ok_or(RenderError::NoGroup(n))?;
+            let inner = matcher::captures(body, value, values)?.ok_or(RenderError::Mismatch(n))?;
+            fixed.insert(n, (value.as_slice(), inner));
+        }
+        if self.expr.is_void() {
+            return Err(RenderError::Unsatisfiable);
+        }
+        let mut out = Vec::new();
+        let mut groups = Captures::new(self.expr.groups());
+        self.walk(self.expr, &mut out, &mut groups, &fixed)?;
+        groups.set(0, Some(0..out.len()));
+        Ok((out, groups))
+    }
+
+    fn walk(
+        &mut self,
+        expr: &Expr,
+        out: &mut Vec<u8>,
+        groups: &mut Captures,
+        fixed: &Fixed,
+    ) -> Result<(), RenderError> {
+        match expr {
+            Expr::Empty => (),
+            Expr::Byte(ch) => out.push(*ch),
+            Expr::Class(set) => out.extend(set.nth(self.rng.below(set.len() as u64) as usize)),
+            Expr::Seq(exprs) => {
+                for e in exprs {
+                    self.walk(e, out, groups, fixed)?;
+                }
+            }
+            Expr::Alt(exprs) => {
+                // Only a branch holding every fixed group here can use them
+                let wanted = pinned(expr, fixed);
+                let live = exprs
+                    .iter()
+                    .filter(|e| !e.is_void() && pinned(e, fixed) == wanted)
+                    .collect::<Vec<_>>();
+                if live.is_empty() {
+                    return Err(RenderError::Unsatisfiable);
+                }
+                let pick = live[self.rng.below(live.len() as u64) as usize];
+                self.walk(pick, out, groups, fixed)?;
+            }
+            Expr::Repeat(expr, min, max) => {
+                // A repeat of something that can't match only allows zero
+                // copies, and one holding fixed groups needs at least one
+                let count = if expr.is_void() {
+                    0
+                } else if pinned(expr, fixed).is_empty() {
+                    self.count(*min, *max)
+                } else if *max == 0 {
+                    return Err(RenderError::Unsatisfiable);
+                } else {
+                    self.count((*min).max(1), *max)
+                };
+                for _ in 0..count {
+                    self.walk(expr, out, groups, fixed)?;
+                }
+            }
+            Expr::Group(n, expr) => {
+                let start = out.len();
+                match fixed.get(n) {
+                    Some((value, inner)) => {
+                        out.extend_from_slice(value);
+                        for (i, span) in inner.spans().iter().enumerate().skip(1) {
+                            if let Some(span) = span {
+                                groups.set(i, Some(start + span.start..start + span.end));
+                            }
+                        }
+                    }
+                    None => self.walk(expr, out, groups, fixed)?,
+                }
+                groups.set(*n, Some(start..out.len()));
+            }
+            Expr::Ref(n) => groups.copy(*n, out),
+        }
+        Ok(())
+    }
+
+    fn count(&mut self, min: usize, max: usize) -> usize {
+        if max < INFINITE as usize {
+            return min + self.rng.below((max - min + 1) as u64) as usize;
+        }
+        let extra = match self.unbounded {
+            Unbounded::Uniform(n) => self.rng.below(n as u64 + 1) as usize,
+            Unbounded::Geometric(mean) if mean > 0.0 => {
+                // Inverse transform of a geometric distribution over 0, 1, 2..
+                let keep = mean / (mean + 1.0);
+                ((1.0 - self.rng.next_f64()).ln() / keep.ln()).floor() as usize
+            }
+            Unbounded::Geometric(_) => 0,
+        };
+        min + extra.min(max - min)
+    }
+}
+
+/// The fixed groups found in an expression, in order.
+fn pinned(expr: &Expr, fixed: &Fixed) -> Vec<usize> {
+    let mut found = Vec::new();
+    let mut stack = vec![expr];
+    while let Some(e) = stack.pop() {
+        match e {
+            Expr::Group(n, _) if fixed.contains_key(n) => found.push(*n),
+            Expr::Group(_, inner) | Expr::Repeat(inner, _, _) => stack.push(inner),
+            Expr::Seq(exprs) | Expr::Alt(exprs) => stack.extend(exprs),
+            Expr::Empty | Expr::Byte(_) | Expr::Class(_) | Expr::Ref(_) => (),
+        }
+    }
+    found.sort_unstable();
+    found
+}
+
+impl<'a> Iterator for Sampler<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.sample()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enumerate::Enumerator;
+    use crate::expr::compile;
+    use crate::parse;
+
+    fn expr(re: &str) -> Expr {
+        compile(&parse(re.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn sample_a() {
+        let expr = expr(r"\w+([-+.]\w+)*@\w+([-.]\w+)*\.\w+");
+        let a = Sampler::new(&expr, 1).take(100).collect::<Vec<_>>();
+        let b = Sampler::new(&expr, 1).take(100).collect::<Vec<_>>();
+        let c = Sampler::new(&expr, 2).take(100).collect::<Vec<_>>();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(a
+            .iter()
+            .all(|s| s.iter().filter(|&&ch| ch == b'@').count() == 1));
+    }
+
+    #[test]
+    fn sample_b() {
+        let expr = expr("(ab|c)[x-z]{1,2}|q?");
+        let all = Enumerator::new(&expr).collect::<Vec<_>>();
+        let mut seen = Sampler::new(&expr, 9).take(2000).collect::<Vec<_>>();
+        assert!(seen.iter().all(|s| all.contains(s)));
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), all.len());
+    }
+
+    #[test]
+    fn sample_c() {
+        let expr = expr("a*");
+        let lens = |unbounded| {
+            Sampler::new(&expr, 3)
+                .unbounded(unbounded)
+                .take(1000)
+                .map(|s| s.len())
+                .collect::<Vec<_>>()
+        };
+        assert!(lens(Unbounded::Uniform(3)).iter().all(|&n| n <= 3));
+        assert_eq!(lens(Unbounded::Uniform(0)), vec![0; 1000]);
+        let geometric = lens(Unbounded::Geometric(4.0));
+        let mean = geometric.iter().sum::<usize>() as f64 / 1000.0;
+        assert!(mean > 3.0 && mean < 5.0);
+    }
+
+    #[test]
+    fn sample_e() {
+        let expr = expr("^#?([a-f0-9]{6}|[a-f0-9]{3})$");
+        let short = |sampler: Sampler| sampler.take(2000).filter(|s| s.len() <= 4).count();
+        assert!(short(Sampler::new(&expr, 4)) > 800);
+        assert!(short(Sampler::new(&expr, 4).uniform(Length::UpTo(7)).unwrap()) < 10);
+        let exact = Sampler::new(&expr, 4).uniform(Length::Exact(4)).unwrap();
+        assert!(exact.take(100).all(|s| s.len() == 4 && s[0] == b'#'));
+    }
+
+    #[test]
+    fn sample_f() {
+        let expr = expr("a|bc|[x-z]{2}|[0-9]+");
+        let mut seen = std::collections::HashMap::new();
+        let sampler = Sampler::new(&expr, 8).uniform(Length::Exact(2)).unwrap();
+        for s in sampler.take(11_000) {
+            *seen.entry(s).or_insert(0) += 1;
+        }
+        assert_eq!(seen.len(), 1 + 9 + 100);
+        assert!(seen.values().all(|&n| n > 40 && n < 180));
+        let mut none = Sampler::new(&expr, 8).uniform(Length::Exact(3)).unwrap();
+        assert_eq!(none.next().map(|s| s.len()), Some(3));
+        let mut none = Sampler::new(&expr, 8).uniform(Length::UpTo(0)).unwrap();
+        assert_eq!(none.next(), None);
+    }
+
+    #[test]
+    fn sample_g() {
+        let expr = expr(r"<([a-z]+)>[0-9]*</\1>");
+        for s in Sampler::new(&expr, 6).take(200) {
+            let s = String::from_utf8(s).unwrap();
+            let open = &s[1..s.find('>').unwrap()];
+            assert!(s.ends_with(&format!("</{}>", open)), "{}", s);
+        }
+        let expr = self::expr(r"(a|b)+\1");
+        let uniform = Sampler::new(&expr, 6).uniform(Length::UpTo(6));
+        assert_eq!(uniform.err(), Some(RenderError::Backreference));
+    }
+
+    #[test]
+    fn sample_h() {
+        let expr = expr(r"^(\d{5}-\d{4}|\d{5}|\d{9})$");
+        for uniform in [false, true] {
+            let mut sampler = Sampler::new(&expr, 10);
+            if uniform {
+                sampler = sampler.uniform(Length::UpTo(10)).unwrap();
+            }
+            for _ in 0..50 {
+                let (s, groups) = sampler.sample_with_captures().unwrap();
+                assert_eq!(groups.spans(), &[Some(0..s.len()), Some(0..s.len())]);
+            }
+        }
+        let expr = self::expr(r"x(a)|y(b)");
+        let mut sampler = Sampler::new(&expr, 11);
+        for _ in 0..20 {
+            let (s, groups) = sampler.sample_with_captures().unwrap();
+            let set = if s[0] == b'x' {
+                [true, false]
+            } else {
+                [false, true]
+            };
+            assert_eq!([groups.get(1).is_some(), groups.get(2).is_some()], set);
+        }
+    }
+
+    #[test]
+    fn sample_i() {
+        let expr = expr(r"(https?)://(www\.)?([a-z]+\.(com|org))(/[a-z]*)?");
+        let values = [(1, "https"), (3, "example.com")]
+            .iter()
+            .map(|(n, v)| (*n, v.as_bytes().to_vec()))
+            .collect::<HashMap<_, _>>();
+        let mut sampler = Sampler::new(&expr, 12);
+        for _ in 0..50 {
+            let (s, groups) = sampler.render(&values).unwrap();
+            let s = String::from_utf8(s).unwrap();
+            assert!(s.starts_with("https://"), "{}", s);
+            assert_eq!(&s[groups.get(3).unwrap()], "example.com");
+            assert_eq!(&s[groups.get(4).unwrap()], "com");
+        }
+    }
+
+    #[test]
+    fn sample_j() {
+        let render = |re: &str, values: &[(usize, &str)]| {
+            let values = values
+                .iter()
+                .map(|(n, v)| (*n, v.as_bytes().to_vec()))
+                .collect::<HashMap<_, _>>();
+            Sampler::new(&expr(re), 13)
+                .render(&values)
+                .map(|(s, _)| String::from_utf8(s).unwrap())
+        };
+        assert_eq!(render("x(a)|y(b)", &[(2, "b")]), Ok("yb".to_string()));
+        assert_eq!(
+            render(r"<([a-z]+)>\1", &[(1, "em")]),
+            Ok("<em>em".to_string())
+        );
+        assert_eq!(render("(a+)*", &[(1, "aa")]).map(|s| s.len() % 2), Ok(0));
+        assert_eq!(
+            render("([0-9]+)", &[(1, "12a")]),
+            Err(RenderError::Mismatch(1))
+        );
+        assert_eq!(render("(a)", &[(2, "a")]), Err(RenderError::NoGroup(2)));
+        assert_eq!(
+            render("x(a)|y(b)", &[(1, "a"), (2, "b")]),
+            Err(RenderError::Unsatisfiable)
+        );
+        assert_eq!(
+            render("((a)b)", &[(1, "ab"), (2, "c")]),
+            Err(RenderError::Mismatch(1))
+        );
+        assert_eq!(
+            render("(a){0}", &[(1, "a")]),
+            Err(RenderError::Unsatisfiable)
+        );
+    }
+
+    #[test]
+    fn sample_d() {
+        assert_eq!(Sampler::new(&expr("a[^ -~]"), 0).next(), None);
+        assert_eq!(
+            Sampler::new(&expr("a[^ -~]?"), 0).next(),
+            Some(b"a".to_vec())
+        );
+        assert_eq!(
+            Sampler::new(&expr("a|[^ -~]"), 0).next(),
+            Some(b"a".to_vec())
+        );
+    }
+
+    #[test]
+    fn sample_k() {
+        // Empty iterations are weighed once, so each string is as likely
+        let expr = expr("(a?){2}b|c");
+        let mut seen = HashMap::new();
+        let sampler = Sampler::new(&expr, 13).uniform(Length::UpTo(3)).unwrap();
+        for s in sampler.take(4000) {
+            *seen.entry(s).or_insert(0) += 1;
+        }
+        assert_eq!(seen.len(), 4);
+        assert!(seen.values().all(|&n| n > 850 && n < 1150), "{:?}", seen);
+        // Strings produced more than one way are as likely as the rest
+        for (re, strings) in &[
+            ("a*a*", 5),
+            ("(a|ab)(c|bc)", 3),
+            (r"(\d|\d\d|[1-9]\d)", 110),
+        ] {
+            let expr = self::expr(re);
+            let mut seen = HashMap::new();
+            let mut sampler = Sampler::new(&expr, 13).uniform(Length::UpTo(4)).unwrap();
+            for _ in 0..strings * 400 {
+                let (s, groups) = sampler.sample_with_captures().unwrap();
+                assert_eq!(groups.get(0), Some(0..s.len()));
+                *seen.entry(s).or_insert(0) += 1;
+            }
+            assert_eq!(seen.len(), *strings, "{}", re);
+            assert!(
+                seen.values().all(|&n| n > 300 && n < 500),
+                "{} {:?}",
+                re,
+                seen
+            );
+        }
+        let expr = self::expr("(a|ab)(c|bc)");
+        let mut sampler = Sampler::new(&expr, 13).uniform(Length::Exact(3)).unwrap();
+        let (s, groups) = sampler.sample_with_captures().unwrap();
+        assert_eq!(
+            (s, groups.get(1), groups.get(2)),
+            (b"abc".to_vec(), Some(0..1), Some(1..3))
+        );
+    }
+}
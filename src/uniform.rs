@@ -0,0 +1,228 @@
+use crate::bigint::BigUint;
+use crate::expr::{Captures, Expr};
+use crate::rng::Rng;
+
+/// How many strings of each length, up to a limit, every sub-expression
+/// produces. Choices weighted by these counts draw each string of a given
+/// length with equal probability.
+///
+/// Repeats count only their non-empty iterations, padding with empty ones
+/// to reach the minimum, so `(a?)*` weighs `aa` once rather than once per
+/// placement of the empty matches. Patterns that stay ambiguous, like
+/// `(a|a)`, still weigh a string once per way of producing it, which is
+/// why [`Sampler::uniform`](crate::Sampler::uniform) walks the pattern's
+/// automaton for them instead.
+pub struct Weights<'a> {
+    expr: &'a Expr,
+    counts: Vec<BigUint>,
+    parts: Parts<'a>,
+}
+
+enum Parts<'a> {
+    Leaf,
+    /// Children, and the counts for each suffix `children[i..]` of the
+    /// sequence.
+    Seq(Vec<Weights<'a>>, Vec<Vec<BigUint>>),
+    Alt(Vec<Weights<'a>>),
+    /// The inner expression, and the counts for exactly `j` non-empty
+    /// iterations at index `j`.
+    Repeat(Box<Weights<'a>>, Vec<Vec<BigUint>>),
+    Group(Box<Weights<'a>>),
+}
+
+/// Counts that are one at `len` and zero elsewhere.
+fn unit(n: usize, len: usize, max_len: usize) -> Vec<BigUint> {
+    let mut counts = vec![BigUint::zero(); max_len + 1];
+    if len <= max_len {
+        counts[len] = BigUint::from(n as u64);
+    }
+    counts
+}
+
+/// Counts for one part followed by another, cut off at the same length.
+fn convolve(a: &[BigUint], b: &[BigUint]) -> Vec<BigUint> {
+    let mut out = vec![BigUint::zero(); a.len()];
+    for (i, x) in a.iter().enumerate().filter(|(_, x)| !x.is_zero()) {
+        for (j, y) in b.iter().enumerate().take(a.len() - i) {
+            if !y.is_zero() {
+                out[i + j] += &(x * y);
+            }
+        }
+    }
+    out
+}
+
+/// Index picked with probability proportional to its weight. The weights
+/// must not all be zero.
+pub(crate) fn choose<I>(rng: &mut Rng, weights: I) -> usize
+where
+    I: Iterator<Item = BigUint>,
+{
+    let weights = weights.collect::<Vec<_>>();
+    let mut total = BigUint::zero();
+    weights.iter().for_each(|w| total += w);
+    let mut r = rng.below_big(&total);
+    for (i, w) in weights.iter().enumerate() {
+        if r < *w {
+            return i;
+        }
+        r = &r - w;
+    }
+    unreachable!("choice beyond the total weight")
+}
+
+impl<'a> Weights<'a> {
+    pub fn new(expr: &'a Expr, max_len: usize) -> Self {
+        let (counts, parts) = match expr {
+            // What a backreference copies isn't known until its group is
+            // sampled, so it weighs like an empty string
+            Expr::Empty | Expr::Ref(_) => (unit(1, 0, max_len), Parts::Leaf),
+            Expr::Byte(_) => (unit(1, 1, max_len), Parts::Leaf),
+            Expr::Class(set) => (unit(set.len(), 1, max_len), Parts::Leaf),
+            Expr::Seq(exprs) => {
+                let children = exprs
+                    .iter()
+                    .map(|e| Weights::new(e, max_len))
+                    .collect::<Vec<_>>();
+                let mut suffixes = vec![unit(1, 0, max_len)];
+                for child in children.iter().rev() {
+                    let next = convolve(&child.counts, &suffixes[suffixes.len() - 1]);
+                    suffixes.push(next);
+                }
+                suffixes.reverse();
+                (suffixes[0].clone(), Parts::Seq(children, suffixes))
+            }
+            Expr::Alt(exprs) => {
+                let children = exprs
+                    .iter()
+                    .map(|e| Weights::new(e, max_len))
+                    .collect::<Vec<_>>();
+                let mut counts = unit(0, 0, max_len);
+                for child in &children {
+                    counts
+                        .iter_mut()
+                        .zip(&child.counts)
+                        .for_each(|(c, n)| *c += n);
+                }
+                (counts, Parts::Alt(children))
+            }
+            Expr::Repeat(inner, min, max) => {
+                let inner = Weights::new(inner, max_len);
+                let mut step = inner.counts.clone();
+                step[0] = BigUint::zero();
+                let mut powers = vec![unit(1, 0, max_len)];
+                while powers.len() <= (*max).min(max_len) {
+                    let next = convolve(&powers[powers.len() - 1], &step);
+                    powers.push(next);
+                }
+                let mut counts = unit(0, 0, max_len);
+                if min <= max {
+                    for power in powers.iter().skip(inner.least(*min)) {
+                        counts.iter_mut().zip(power).for_each(|(c, n)| *c += n);
+                    }
+                }
+                (counts, Parts::Repeat(Box::new(inner), powers))
+            }
+            Expr::Group(_, inner) => {
+                let inner = Weights::new(inner, max_len);
+                (inner.counts.clone(), Parts::Group(Box::new(inner)))
+            }
+        };
+        Self {
+            expr,
+            counts,
+            parts,
+        }
+    }
+
+    /// Number of strings of each length, from zero up to the limit.
+    pub fn counts(&self) -> &[BigUint] {
+        &self.counts
+    }
+
+    /// Fewest non-empty iterations a repeat of this expression needs to
+    /// reach `min` iterations.
+    fn least(&self, min: usize) -> usize {
+        if self.counts[0].is_zero() {
+            min
+        } else {
+            0
+        }
+    }
+
+    /// Append a string of exactly `len` bytes, not counting backreferences.
+    /// There must be at least one.
+    pub fn sample(&self, len: usize, rng: &mut Rng, out: &mut Vec<u8>, groups: &mut Captures) {
+        match (self.expr, &self.parts) {
+            (Expr::Byte(ch), _) => out.push(*ch),
+            (Expr::Ref(n), _) => groups.copy(*n, out),
+            (Expr::Class(set), _) => out.extend(set.nth(rng.below(set.len() as u64) as usize)),
+            (_, Parts::Seq(children, suffixes)) => {
+                let mut left = len;
+                for (child, rest) in children.iter().zip(&suffixes[1..]) {
+                    let m = choose(rng, (0..=left).map(|m| &child.counts[m] * &rest[left - m]));
+                    child.sample(m, rng, out, groups);
+                    left -= m;
+                }
+            }
+            (_, Parts::Alt(children)) => {
+                let i = choose(rng, children.iter().map(|c| c.counts[len].clone()));
+                children[i].sample(len, rng, out, groups);
+            }
+            (Expr::Repeat(_, min, _), Parts::Repeat(inner, powers)) => {
+                let least = inner.least(*min);
+                let count = least + choose(rng, powers.iter().skip(least).map(|p| p[len].clone()));
+                let mut left = len;
+                for done in (0..count).rev() {
+                    let m = 1 + choose(
+                        rng,
+                        (1..=left).map(|m| &inner.counts[m] * &powers[done][left - m]),
+                    );
+                    inner.sample(m, rng, out, groups);
+                    left -= m;
+                }
+                // Pad with empty iterations up to the minimum
+                for _ in count..*min {
+                    inner.sample(0, rng, out, groups);
+                }
+            }
+            (Expr::Group(n, _), Parts::Group(inner)) => {
+                let start = out.len();
+                inner.sample(len, rng, out, groups);
+                groups.set(*n, Some(start..out.len()));
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::compile;
+    use crate::parse;
+
+    fn counts(re: &str, max_len: usize) -> Vec<u64> {
+        let expr = compile(&parse(re.as_bytes()).unwrap());
+        let weights = Weights::new(&expr, max_len);
+        weights
+            .counts()
+            .iter()
+            .map(|n| n.to_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn weights_a() {
+        assert_eq!(counts("ab|c|", 3), vec![1, 1, 1, 0]);
+        assert_eq!(counts("[a-c]{1,2}x?", 4), vec![0, 3, 12, 9, 0]);
+        assert_eq!(counts("(a?)*", 3), vec![1, 1, 1, 1]);
+        assert_eq!(counts("(a?){2}b", 3), vec![0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn weights_b() {
+        assert_eq!(counts("a[^ -~]|[^ -~]", 2), vec![0, 0, 0]);
+        assert_eq!(counts("(ab|c)+", 4), vec![0, 1, 2, 3, 5]);
+    }
+}